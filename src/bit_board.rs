@@ -1,14 +1,14 @@
 use std::cmp::min;
-use crate::position::{Position, BOARD_SIZE, HEIGHT, WIDTH, BoardType};
+use crate::position::{Position, Dimensions, BoardBits, GameStatus, Player};
 
-// position/mask are 49-bit representations of the board
+// position/mask are width * (height + 1) bit representations of the board
 // position:
 //     empty spaces and current player's pieces are '0's
 //     opponent's pieces are '1's
 // mask:
 //     empty spaces are '0's
 //     all pieces are '1's
-// the bottom left of the board is the first bit (0) and the top right is the final bit (48)
+// the bottom left of the board is the first bit (0) and the top right is the final bit (48 on a 7x6 board)
 // this means each step up a column is equivalent to multiplying by two numerically
 // the top row is needed, but not part of the game board
 //                              .  .  .  .  .  .  .
@@ -18,31 +18,55 @@ use crate::position::{Position, BOARD_SIZE, HEIGHT, WIDTH, BoardType};
 //                              2  9 16 23 30 37 44
 //                              1  8 15 22 29 36 43
 //                              0  7 14 21 28 35 42
+// boards with more than 64 bits, such as 8x8 or 9x7, need the wider backing storage
+pub type WideBitBoard = BitBoard<u128>;
+
 #[derive(Clone)]
-pub struct BitBoard {
-    pub position: BoardType,
-    pub mask: BoardType,
+pub struct BitBoard<B: BoardBits = u64> {
+    pub position: B,
+    pub mask: B,
     pub move_count: u8,
+    dimensions: Dimensions,
+    bottom_mask: B,
+    board_mask: B,
 }
 
-impl Position for BitBoard {
+impl<B: BoardBits> Position for BitBoard<B> {
+    type Bits = B;
+
+    fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
     fn can_play(&self, col: u8) -> bool {
-        self.mask & Self::top_mask(col) == 0
+        self.mask & self.top_mask(col) == B::ZERO
     }
 
     fn play(&mut self, col: u8) {
         debug_assert!(self.can_play(col));
-        self.position ^= self.mask;
-        self.mask |= self.mask + Self::bottom_mask(col);
+        self.position = self.position ^ self.mask;
+        self.mask = self.mask | (self.mask + self.bottom_mask(col));
         self.move_count += 1;
     }
 
+    fn can_undo(&self, col: u8) -> bool {
+        col < self.dimensions.width && self.mask & self.column_mask(col) != B::ZERO
+    }
+
+    fn undo(&mut self, col: u8) {
+        debug_assert!(self.can_undo(col));
+        let top_piece = ((self.mask & self.column_mask(col)) + self.bottom_mask(col)) >> 1;
+        self.mask = self.mask ^ top_piece;
+        self.position = self.position ^ self.mask;
+        self.move_count -= 1;
+    }
+
     fn is_winning_move(&self, col: u8) -> bool {
         let new_position = self.position_from_col(col);
-        return Self::alignment_horizontal(new_position)
-            || Self::alignment_positive_diagonal(new_position)
-            || Self::alignment_negative_diagonal(new_position)
-            || Self::alignment_vertical(new_position);
+        self.alignment_horizontal(new_position)
+            || self.alignment_positive_diagonal(new_position)
+            || self.alignment_negative_diagonal(new_position)
+            || Self::alignment_vertical(new_position)
     }
 
     fn get_move_count(&self) -> u8 {
@@ -51,10 +75,10 @@ impl Position for BitBoard {
 
     fn get_moves_with_weight(&self) -> Vec<(u8, u8)> {
         let mut possible_mask = self.possible();
-        let opponent_wins = Self::winning_positions(self.position ^ self.mask, self.mask);
+        let opponent_wins = self.winning_positions(self.position ^ self.mask, self.mask);
         let forced_moves = possible_mask & opponent_wins;
-        if forced_moves > 0 {
-            if (forced_moves & (forced_moves - 1)) > 0 {
+        if forced_moves != B::ZERO {
+            if (forced_moves & (forced_moves - B::ONE)) != B::ZERO {
                 return Vec::new();
             }
             else {
@@ -63,175 +87,212 @@ impl Position for BitBoard {
         }
         let non_losing_moves = possible_mask & !(opponent_wins >> 1);
 
-        (0..WIDTH)
-            .filter(|col| (self.column_mask(*col) & non_losing_moves) > 0 && self.can_play(*col))
-            .map(|col| (col, Self::winning_positions(self.position_from_col(col), self.mask).count_ones() as u8))
+        (0..self.dimensions.width)
+            .filter(|col| (self.column_mask(*col) & non_losing_moves) != B::ZERO && self.can_play(*col))
+            .map(|col| (col, self.winning_positions(self.position_from_col(col), self.mask).count_ones() as u8))
             .collect::<Vec<_>>()
     }
 
     fn can_win_next_move(&self) -> bool {
-        for col in 0..WIDTH {
-            if self.is_winning_move(col) && self.can_play(col) {
-                return true;
-            }
-        }
-        return false;
+        (0..self.dimensions.width).any(|col| self.is_winning_move(col) && self.can_play(col))
     }
 
-    fn key(&self) -> BoardType {
+    fn key(&self) -> B {
         self.position + self.mask
     }
 
-    fn symmetric_key(&self) -> BoardType {
+    fn symmetric_key(&self) -> B {
         min(self.key(), self.reverse_key())
     }
 
     fn stringify(&self) -> String {
         String::from(self)
     }
-}
 
-impl BitBoard {
-    const BOTTOM_MASK: BoardType = {
-        let mut bottom_mask = 0;
-        let mut col = 0;
-        while col < WIDTH {
-            bottom_mask += 1 << (col * (HEIGHT + 1));
-            col += 1;
+    fn status(&self) -> GameStatus {
+        let next_player = Player::to_move(self.move_count);
+        let pieces = [(next_player.other(), self.position ^ self.mask), (next_player, self.position)];
+        for (player, player_pieces) in pieces {
+            if let Some(line) = self.winning_line(player_pieces) {
+                return GameStatus::Win { player, line };
+            }
         }
-        bottom_mask
-    };
+        if self.move_count == self.dimensions.board_size() {
+            GameStatus::Draw
+        }
+        else {
+            GameStatus::Ongoing
+        }
+    }
+}
 
-    const BOARD_MASK: BoardType = Self::BOTTOM_MASK * ((1 << HEIGHT) - 1);
+impl Default for BitBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl BitBoard {
     pub fn new() -> Self {
+        Self::with_dimensions(Dimensions::STANDARD)
+    }
+}
+
+impl<B: BoardBits> BitBoard<B> {
+    pub fn with_dimensions(dimensions: Dimensions) -> Self {
+        assert!(dimensions.fits::<B>(), "A {} board needs {} bits", dimensions, dimensions.bits());
+        let mut bottom_mask = B::ZERO;
+        for col in 0..dimensions.width {
+            bottom_mask = bottom_mask | (B::ONE << (col * (dimensions.height + 1)));
+        }
         BitBoard {
-            position: 0,
-            mask: 0,
+            position: B::ZERO,
+            mask: B::ZERO,
             move_count: 0,
+            dimensions,
+            bottom_mask,
+            board_mask: bottom_mask * ((B::ONE << dimensions.height) - B::ONE),
         }
     }
 
-    fn possible(&self) -> BoardType {
-        (self.mask + Self::BOTTOM_MASK) & Self::BOARD_MASK
+    // checks a position built piece by piece could have been reached by playing moves
+    pub fn validate(&self) -> Result<(), String> {
+        if (self.mask + self.bottom_mask) & self.mask != B::ZERO {
+            return Err("Position has floating pieces".to_string());
+        }
+        let next_player = Player::to_move(self.move_count);
+        if self.winning_line(self.position).is_some() {
+            return Err(format!("Player {} already won but the game continued", next_player));
+        }
+        let last_pieces = self.position ^ self.mask;
+        if self.winning_line(last_pieces).is_some() {
+            // one of the last player's top pieces must have been the winning move
+            let could_be_last_move = (0..self.dimensions.width)
+                .filter(|col| self.can_undo(*col))
+                .map(|col| ((self.mask & self.column_mask(col)) + self.bottom_mask(col)) >> 1)
+                .any(|top_piece| top_piece & last_pieces != B::ZERO
+                    && self.winning_line(last_pieces ^ top_piece).is_none());
+            if !could_be_last_move {
+                return Err(format!("Player {} already won but the game continued", next_player.other()));
+            }
+        }
+        Ok(())
+    }
+
+    fn winning_line(&self, pieces: B) -> Option<[(u8, u8); 4]> {
+        let height = self.dimensions.height;
+        for x in [1, height + 1, height + 2, height] {
+            let m = pieces & (pieces >> x);
+            let line_starts = m & (m >> (2 * x));
+            if line_starts != B::ZERO {
+                let start = line_starts.trailing_zeros() as u8;
+                return Some([0, 1, 2, 3].map(|i| {
+                    let bit = start + i * x;
+                    (bit / (height + 1), bit % (height + 1))
+                }));
+            }
+        }
+        None
+    }
+
+    fn possible(&self) -> B {
+        (self.mask + self.bottom_mask) & self.board_mask
     }
 
-    fn column_mask(&self, col: u8) -> BoardType {
-        ((1 << HEIGHT) - 1) << (col * (HEIGHT + 1))
+    fn column_mask(&self, col: u8) -> B {
+        ((B::ONE << self.dimensions.height) - B::ONE) << (col * (self.dimensions.height + 1))
     }
 
-    fn reverse_key(&self) -> BoardType {
-        let mut position: BoardType = 0;
-        let mut mask: BoardType = 0;
-        for col in (0..WIDTH).rev() {
+    fn reverse_key(&self) -> B {
+        let width = self.dimensions.width as i16;
+        let column_height = self.dimensions.height as i16 + 1;
+        let mut position = B::ZERO;
+        let mut mask = B::ZERO;
+        for col in (0..self.dimensions.width).rev() {
             let column_mask = self.column_mask(col);
-            let shift: i8 = (2 * (col as i8) - (WIDTH as i8 - 1)) * WIDTH as i8;
+            let shift = (2 * (col as i16) - (width - 1)) * column_height;
             let position_col_value = self.position & column_mask;
             let mask_col_value = self.mask & column_mask;
             if shift > 0 {
-                position += position_col_value >> shift;
-                mask += mask_col_value >> shift;
+                position = position + (position_col_value >> shift as u8);
+                mask = mask + (mask_col_value >> shift as u8);
             }
             else {
-                let abs_shift = shift.abs();
-                position += position_col_value << abs_shift;
-                mask += mask_col_value << abs_shift;
+                let abs_shift = shift.unsigned_abs() as u8;
+                position = position + (position_col_value << abs_shift);
+                mask = mask + (mask_col_value << abs_shift);
             }
         }
         position + mask
     }
 
-    fn winning_positions(position: BoardType, mask: BoardType) -> BoardType {
+    fn winning_positions(&self, position: B, mask: B) -> B {
+        let height = self.dimensions.height;
         let all_winning_positions = Self::vertical_winning_positions(position)
-            | Self::horizontal_winning_positions(position)
-            | Self::positive_diagonal_winning_positions(position)
-            | Self::negative_diagonal_winning_positions(position);
-        all_winning_positions & (Self::BOARD_MASK ^ mask)
+            | Self::line_winning_positions(position, height + 1)
+            | Self::line_winning_positions(position, height + 2)
+            | Self::line_winning_positions(position, height);
+        all_winning_positions & (self.board_mask ^ mask)
     }
 
-    fn vertical_winning_positions(position: BoardType) -> BoardType {
+    fn vertical_winning_positions(position: B) -> B {
         (position << 1) & (position << 2) & (position << 3)
     }
 
-    fn horizontal_winning_positions(position: BoardType) -> BoardType {
-        let x = HEIGHT + 1;
-        let x3 = x * 3;
-        let m = (position << x) & (position << 2 * x);
-        let n = m >> x3;
-        return m & (position << x3)
-            | m & (position >> x)
-            | n & (position << x)
-            | n & (position >> x3)
-    }
-
-    fn positive_diagonal_winning_positions(position: BoardType) -> BoardType {
-        let x = HEIGHT + 2;
-        let x3 = x * 3;
-        let m = (position << x) & (position << (2 * x));
-        let n = m >> x3;
-        return m & (position << x3)
-            | m & (position >> x)
-            | n & (position << x)
-            | n & (position >> x3)
-    }
-
-    fn negative_diagonal_winning_positions(position: BoardType) -> BoardType {
-        let x = HEIGHT;
+    // x is the bit distance between neighbouring cells of a line:
+    // height + 1 for horizontal, height + 2 for positive diagonal and height for negative diagonal
+    // n is shifted down from position rather than from m, which would lose the top columns of a board that fills B
+    fn line_winning_positions(position: B, x: u8) -> B {
         let x3 = x * 3;
         let m = (position << x) & (position << (2 * x));
-        let n = m >> x3;
-        return m & (position << x3)
-            | m & (position >> x)
-            | n & (position << x)
-            | n & (position >> x3)
+        let n = (position >> x) & (position >> (2 * x));
+        (m & (position << x3))
+            | (m & (position >> x))
+            | (n & (position << x))
+            | (n & (position >> x3))
     }
 
-    fn position_from_col(&self, col: u8) -> BoardType {
+    fn position_from_col(&self, col: u8) -> B {
         let flipped_position = self.position ^ self.mask;
-        let new_mask = self.mask | (self.mask + Self::bottom_mask(col));
+        let new_mask = self.mask | (self.mask + self.bottom_mask(col));
         flipped_position ^ new_mask
     }
 
-    fn bottom_mask(col: u8) -> BoardType {
-        1 << col*(HEIGHT + 1)
+    fn bottom_mask(&self, col: u8) -> B {
+        B::ONE << (col * (self.dimensions.height + 1))
     }
 
-    fn top_mask(col: u8) -> BoardType {
-        (1 << (HEIGHT - 1)) << (col * (HEIGHT + 1))
+    fn top_mask(&self, col: u8) -> B {
+        (B::ONE << (self.dimensions.height - 1)) << (col * (self.dimensions.height + 1))
     }
 
-    fn alignment_horizontal(position: BoardType) -> bool {
-        let x = HEIGHT + 1;
-        let m = position & (position >> x);
-        m & (m >> (2 * x)) > 0
+    fn alignment_horizontal(&self, position: B) -> bool {
+        Self::alignment(position, self.dimensions.height + 1)
     }
 
-    fn alignment_positive_diagonal(position: BoardType) -> bool {
-        let x = HEIGHT + 2;
-        let m = position & (position >> x);
-        m & (m >> (2 * x)) > 0
+    fn alignment_positive_diagonal(&self, position: B) -> bool {
+        Self::alignment(position, self.dimensions.height + 2)
     }
 
-    fn alignment_negative_diagonal(position: BoardType) -> bool {
-        let x = HEIGHT;
-        let m = position & (position >> x);
-        m & (m >> (2 * x)) > 0
+    fn alignment_negative_diagonal(&self, position: B) -> bool {
+        Self::alignment(position, self.dimensions.height)
     }
 
-    fn alignment_vertical(position: BoardType) -> bool {
-        let x = 1;
-        let m = position & (position >> x);
-        m & (m >> (2 * x)) > 0
+    fn alignment_vertical(position: B) -> bool {
+        Self::alignment(position, 1)
     }
 
-
+    fn alignment(position: B, x: u8) -> bool {
+        let m = position & (position >> x);
+        m & (m >> (2 * x)) != B::ZERO
+    }
 }
 
-impl From<&BitBoard> for String {
-    fn from(value: &BitBoard) -> Self {
+impl<B: BoardBits> From<&BitBoard<B>> for String {
+    fn from(value: &BitBoard<B>) -> Self {
         let player;
         let opponent;
-        if value.move_count % 2 == 0 {
+        if value.move_count.is_multiple_of(2) {
             player = '2';
             opponent = '1';
         }
@@ -239,11 +300,11 @@ impl From<&BitBoard> for String {
             player = '1';
             opponent = '2';
         }
-        mask_stringify(|mask|
-            if mask & value.mask == 0 {
+        mask_stringify(value.dimensions, |mask: B|
+            if mask & value.mask == B::ZERO {
                 '0'
             }
-            else if mask & value.position == 0 {
+            else if mask & value.position == B::ZERO {
                 player
             }
             else {
@@ -253,23 +314,24 @@ impl From<&BitBoard> for String {
     }
 }
 
-fn mask_stringify(func: impl Fn(BoardType) -> char) -> String {
-    let mut s = String::with_capacity(((BOARD_SIZE * 2) + HEIGHT) as usize);
-    for row in (0..HEIGHT).rev() {
-        for col in 0..WIDTH {
-            s.push(func(1 << (WIDTH * col + row)));
+fn mask_stringify<B: BoardBits>(dimensions: Dimensions, func: impl Fn(B) -> char) -> String {
+    let Dimensions { width, height } = dimensions;
+    let mut s = String::with_capacity(((dimensions.board_size() as usize) * 2) + height as usize);
+    for row in (0..height).rev() {
+        for col in 0..width {
+            s.push(func(B::ONE << ((height + 1) * col + row)));
             s.push(' ');
         }
         s.push('\n');
     }
-    return s;
+    s
 }
 
 // very useful for debugging
 #[allow(dead_code)]
-fn stringify_position(position: BoardType) -> String {
-    mask_stringify(|mask|
-        if position & mask > 0 {
+fn stringify_position<B: BoardBits>(dimensions: Dimensions, position: B) -> String {
+    mask_stringify(dimensions, |mask|
+        if position & mask != B::ZERO {
             '1'
         }
         else {
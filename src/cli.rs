@@ -0,0 +1,242 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+// a --flag, switches have no value
+#[derive(Debug)]
+pub struct Flag {
+    pub name: &'static str,
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+// a subcommand with the positional arguments it needs and the flags it accepts besides the global ones
+#[derive(Debug)]
+pub struct Command {
+    pub name: &'static str,
+    pub arguments: &'static [&'static str],
+    pub about: &'static str,
+    pub flags: &'static [Flag],
+}
+
+impl Command {
+    pub fn usage(&self, program: &str) -> String {
+        let mut usage = format!("{} {}", program, self.name);
+        for argument in self.arguments {
+            usage.push_str(&format!(" <{}>", argument));
+        }
+        if !self.flags.is_empty() {
+            usage.push_str(" [flags]");
+        }
+        usage
+    }
+
+    pub fn help(&self, program: &str, global_flags: &[Flag]) -> String {
+        let mut help = format!("{}\n\nUsage: {}\n", self.about, self.usage(program));
+        if !self.flags.is_empty() {
+            help.push_str("\nFlags:\n");
+            help.push_str(&flag_lines(self.flags));
+        }
+        help.push_str("\nGlobal flags:\n");
+        help.push_str(&flag_lines(global_flags));
+        help
+    }
+}
+
+pub fn overview(program: &str, about: &str, commands: &[Command], global_flags: &[Flag]) -> String {
+    let mut help = format!("{}\n\nUsage: {} <command> [arguments] [flags]\n\nCommands:\n", about, program);
+    let width = commands.iter().map(|command| command.name.len()).max().unwrap_or(0);
+    for command in commands {
+        help.push_str(&format!("  {:width$}  {}\n", command.name, command.about, width = width));
+    }
+    help.push_str("\nGlobal flags:\n");
+    help.push_str(&flag_lines(global_flags));
+    help.push_str(&format!("\nRun '{} <command> --help' for the arguments and flags of a command\n", program));
+    help
+}
+
+fn flag_lines(flags: &[Flag]) -> String {
+    let names = flags.iter()
+        .map(|flag| match flag.value {
+            Some(value) => format!("--{} <{}>", flag.name, value),
+            None => format!("--{}", flag.name),
+        })
+        .collect::<Vec<String>>();
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    names.iter().zip(flags)
+        .map(|(name, flag)| format!("  {:width$}  {}\n", name, flag.help, width = width))
+        .collect()
+}
+
+// usage errors come from the command line itself, failures from running the command
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl CliError {
+    pub const USAGE_EXIT_CODE: i32 = 2;
+
+    pub const FAILED_EXIT_CODE: i32 = 1;
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => Self::USAGE_EXIT_CODE,
+            CliError::Failed(_) => Self::FAILED_EXIT_CODE,
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        CliError::Failed(message)
+    }
+}
+
+// a command line split into its command, positional arguments and flags
+// global flags may come anywhere, the flags of a command only after it
+#[derive(Debug, Default)]
+pub struct Args {
+    command: Option<&'static Command>,
+    positionals: Vec<String>,
+    values: Vec<(&'static str, String)>,
+    switches: Vec<&'static str>,
+    help: bool,
+}
+
+impl Args {
+    // args excludes the program name, flag values are given as --name value or --name=value
+    pub fn parse(args: &[String], commands: &'static [Command], global_flags: &'static [Flag]) -> Result<Args, CliError> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                parsed.help = true;
+                continue;
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                match parsed.command {
+                    None => {
+                        let command = commands.iter().find(|command| command.name == arg)
+                            .ok_or_else(|| CliError::Usage(format!("'{}' is not a command", arg)))?;
+                        parsed.command = Some(command);
+                    },
+                    Some(command) if parsed.positionals.len() < command.arguments.len() => parsed.positionals.push(arg.clone()),
+                    Some(command) => return Err(CliError::Usage(format!("Unexpected argument '{}' for {}", arg, command.name))),
+                }
+                continue;
+            };
+
+            let (name, inline_value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let command_flags = parsed.command.map_or(&[][..], |command| command.flags);
+            let flag = global_flags.iter().chain(command_flags)
+                .find(|flag| flag.name == name)
+                .ok_or_else(|| match parsed.command {
+                    Some(command) => CliError::Usage(format!("--{} is not a flag of {}", name, command.name)),
+                    None => CliError::Usage(format!("--{} is not a flag", name)),
+                })?;
+            match (flag.value, inline_value) {
+                (Some(_), Some(value)) => parsed.values.push((flag.name, value)),
+                (Some(value_name), None) => match args.next() {
+                    Some(value) => parsed.values.push((flag.name, value.clone())),
+                    None => return Err(CliError::Usage(format!("--{} needs a <{}>", flag.name, value_name))),
+                },
+                (None, Some(_)) => return Err(CliError::Usage(format!("--{} does not take a value", flag.name))),
+                (None, None) => parsed.switches.push(flag.name),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn command(&self) -> Option<&'static Command> {
+        self.command
+    }
+
+    pub fn help(&self) -> bool {
+        self.help
+    }
+
+    pub fn positional(&self, index: usize) -> Result<&str, CliError> {
+        match (self.positionals.get(index), self.command) {
+            (Some(positional), _) => Ok(positional.as_str()),
+            (None, Some(command)) => Err(CliError::Usage(format!("{} needs a <{}>", command.name, command.arguments[index]))),
+            (None, None) => Err(CliError::Usage("No command given".to_string())),
+        }
+    }
+
+    pub fn positional_as<T: FromStr<Err: Display>>(&self, index: usize) -> Result<T, CliError> {
+        let positional = self.positional(index)?;
+        let name = self.command.map_or("argument", |command| command.arguments[index]);
+        positional.parse::<T>()
+            .map_err(|error| invalid_value(format!("<{}>", name).as_str(), positional, error))
+    }
+
+    pub fn switch(&self, name: &str) -> bool {
+        self.switches.contains(&name)
+    }
+
+    // every value of a flag that can be given several times, in order
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.values.iter()
+            .filter(move |(flag, _)| *flag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // the last value wins when a flag is given twice
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.iter()
+            .rev()
+            .find(|(flag, _)| *flag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn value_as<T: FromStr<Err: Display>>(&self, name: &str) -> Result<Option<T>, CliError> {
+        self.value(name)
+            .map(|value| value.parse::<T>()
+                .map_err(|error| invalid_value(format!("--{}", name).as_str(), value, error)))
+            .transpose()
+    }
+}
+
+// the parse errors of this crate already quote the value, those of std do not
+fn invalid_value(name: &str, value: &str, error: impl Display) -> CliError {
+    let error = error.to_string();
+    if error.contains(format!("'{}'", value).as_str()) {
+        CliError::Usage(format!("Invalid {}: {}", name, error))
+    }
+    else {
+        CliError::Usage(format!("Invalid {} '{}': {}", name, value, error))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+use crate::position::{GameStatus, Position};
+use crate::solver::Solver;
+
+// How far the engine strays from the best move
+// With probability blunder_probability it plays any legal column, otherwise it samples columns
+// by a softmax over their analyze scores. A temperature of 0 always picks a best scoring column
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Strength {
+    pub temperature: f64,
+    pub blunder_probability: f64,
+}
+
+impl Strength {
+    pub const PERFECT: Strength = Strength { temperature: 0.0, blunder_probability: 0.0 };
+
+    pub const HARD: Strength = Strength { temperature: 0.5, blunder_probability: 0.02 };
+
+    pub const MEDIUM: Strength = Strength { temperature: 1.5, blunder_probability: 0.1 };
+
+    pub const EASY: Strength = Strength { temperature: 4.0, blunder_probability: 0.3 };
+}
+
+impl Default for Strength {
+    fn default() -> Self {
+        Self::PERFECT
+    }
+}
+
+impl std::str::FromStr for Strength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perfect" => Ok(Self::PERFECT),
+            "hard" => Ok(Self::HARD),
+            "medium" => Ok(Self::MEDIUM),
+            "easy" => Ok(Self::EASY),
+            _ => Err(format!("'{}' is not a level, expected easy, medium, hard or perfect", s)),
+        }
+    }
+}
+
+// SplitMix64, small and good enough to pick moves, the same seed always plays the same game
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// a Solver that plays at a given strength
+pub struct Engine {
+    solver: Solver,
+    strength: Strength,
+    rng: Rng,
+}
+
+impl Engine {
+    pub fn new(solver: Solver, strength: Strength, seed: u64) -> Self {
+        Self {
+            solver,
+            strength,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn solver(&self) -> &Solver {
+        &self.solver
+    }
+
+    pub fn strength(&self) -> Strength {
+        self.strength
+    }
+
+    // None once no column can be played
+    pub fn choose_move(&mut self, position: impl Position) -> Option<u8> {
+        let scores = self.solver.analyze(position)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(col, score)| Some((col as u8, score?)))
+            .collect::<Vec<(u8, i8)>>();
+        let best = scores.iter().map(|(_, score)| *score).max()?;
+
+        if self.rng.next_f64() < self.strength.blunder_probability {
+            let index = (self.rng.next_u64() % scores.len() as u64) as usize;
+            return Some(scores[index].0);
+        }
+        if self.strength.temperature <= 0.0 {
+            return scores.iter().find(|(_, score)| *score == best).map(|(col, _)| *col);
+        }
+
+        let weights = scores.iter()
+            .map(|(_, score)| ((*score - best) as f64 / self.strength.temperature).exp())
+            .collect::<Vec<f64>>();
+        let mut target = self.rng.next_f64() * weights.iter().sum::<f64>();
+        for ((col, _), weight) in scores.iter().zip(weights) {
+            if target < weight {
+                return Some(*col);
+            }
+            target -= weight;
+        }
+        scores.last().map(|(col, _)| *col)
+    }
+}
+
+// results of a match, from the point of view of the first engine
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MatchStats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub moves: [u32; 2],
+    pub time: [Duration; 2],
+}
+
+impl MatchStats {
+    // mean time each engine took to choose a move
+    pub fn time_per_move(&self) -> [Duration; 2] {
+        [0, 1].map(|engine| self.time[engine] / self.moves[engine].max(1))
+    }
+}
+
+impl std::fmt::Display for MatchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [first, second] = self.time_per_move();
+        write!(f, "W/D/L: {}/{}/{}, time per move: {:.3}ms vs {:.3}ms",
+            self.wins, self.draws, self.losses, first.as_secs_f64() * 1000.0, second.as_secs_f64() * 1000.0)
+    }
+}
+
+// plays games between two engines, each opening twice so that both engines start it once
+// openings must not be empty or hold finished games
+pub fn play_match<P: Position>(engines: [&mut Engine; 2], openings: &[P], games: usize) -> MatchStats {
+    assert!(!openings.is_empty(), "a match needs at least one opening");
+    let mut stats = MatchStats::default();
+    for game in 0..games {
+        let mut position = openings[(game / 2) % openings.len()].clone();
+        // the engine to move in the opening alternates every game
+        let first = game % 2;
+        let mut turn = first;
+        while position.status() == GameStatus::Ongoing {
+            let start = Instant::now();
+            let col = engines[turn].choose_move(position.clone()).unwrap();
+            stats.time[turn] += start.elapsed();
+            stats.moves[turn] += 1;
+            position.play(col);
+            turn = 1 - turn;
+        }
+        match position.status() {
+            // the engine that just moved won
+            GameStatus::Win { .. } if turn == 1 => stats.wins += 1,
+            GameStatus::Win { .. } => stats.losses += 1,
+            _ => stats.draws += 1,
+        }
+    }
+    stats
+}
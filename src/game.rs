@@ -0,0 +1,130 @@
+use crate::bit_board::BitBoard;
+use crate::position::{Position, Dimensions, BoardBits, GameStatus};
+
+// A BitBoard that remembers the columns played to reach it
+// moves are stored in the same format position_factory::create accepts
+#[derive(Clone)]
+pub struct Game<B: BoardBits = u64> {
+    board: BitBoard<B>,
+    moves: Vec<u8>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self::with_dimensions(Dimensions::STANDARD)
+    }
+}
+
+impl<B: BoardBits> Game<B> {
+    pub fn with_dimensions(dimensions: Dimensions) -> Self {
+        Self {
+            board: BitBoard::with_dimensions(dimensions),
+            moves: Vec::new(),
+        }
+    }
+
+    pub fn board(&self) -> &BitBoard<B> {
+        &self.board
+    }
+
+    pub fn into_board(self) -> BitBoard<B> {
+        self.board
+    }
+
+    pub fn moves(&self) -> &[u8] {
+        &self.moves
+    }
+
+    pub fn last_move(&self) -> Option<u8> {
+        self.moves.last().copied()
+    }
+
+    // takes back the last move, returning the column it was played in
+    pub fn take_back(&mut self) -> Option<u8> {
+        let col = self.moves.pop()?;
+        self.board.undo(col);
+        Some(col)
+    }
+
+    // the game as it was after the first `ply` moves
+    pub fn replay(&self, ply: usize) -> Option<Self> {
+        if ply > self.moves.len() {
+            return None;
+        }
+        let mut game = Self::with_dimensions(self.board.dimensions());
+        for col in &self.moves[..ply] {
+            game.play(*col);
+        }
+        Some(game)
+    }
+
+    pub fn move_string(&self) -> String {
+        self.moves.iter()
+            .map(|col| (b'0' + col) as char)
+            .collect()
+    }
+}
+
+impl<B: BoardBits> Position for Game<B> {
+    type Bits = B;
+
+    fn dimensions(&self) -> Dimensions {
+        self.board.dimensions()
+    }
+
+    fn can_play(&self, col: u8) -> bool {
+        self.board.can_play(col)
+    }
+
+    fn play(&mut self, col: u8) {
+        self.board.play(col);
+        self.moves.push(col);
+    }
+
+    fn can_undo(&self, col: u8) -> bool {
+        self.last_move() == Some(col)
+    }
+
+    fn undo(&mut self, col: u8) {
+        debug_assert!(self.can_undo(col));
+        self.take_back();
+    }
+
+    fn is_winning_move(&self, col: u8) -> bool {
+        self.board.is_winning_move(col)
+    }
+
+    fn get_move_count(&self) -> u8 {
+        self.board.get_move_count()
+    }
+
+    fn get_moves_with_weight(&self) -> Vec<(u8, u8)> {
+        self.board.get_moves_with_weight()
+    }
+
+    fn can_win_next_move(&self) -> bool {
+        self.board.can_win_next_move()
+    }
+
+    fn key(&self) -> B {
+        self.board.key()
+    }
+
+    fn symmetric_key(&self) -> B {
+        self.board.symmetric_key()
+    }
+
+    fn stringify(&self) -> String {
+        self.board.stringify()
+    }
+
+    fn status(&self) -> GameStatus {
+        self.board.status()
+    }
+}
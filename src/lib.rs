@@ -2,6 +2,9 @@ pub mod solver;
 pub mod position;
 pub mod transposition_table;
 pub mod bit_board;
+pub mod game;
 pub mod position_factory;
 pub mod opening_book;
 pub mod reader;
+pub mod engine;
+pub mod cli;
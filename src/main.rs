@@ -1,90 +1,635 @@
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use connect4::opening_book::{Explorer, get_opening_book, save_opening_book};
-use connect4::{position_factory, reader};
-use connect4::position::Position;
-use connect4::solver::Solver;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use connect4::cli::{self, Args, CliError, Command, Flag, OutputFormat};
+use connect4::opening_book::{self, BookError, Explorer, OpeningBook, load_opening_book, save_opening_book};
+use connect4::{position_factory, reader, transposition_table};
+use connect4::engine::{self, Engine, Strength};
+use connect4::game::Game;
+use connect4::position::{Position, Dimensions, BoardBits, GameStatus, Player};
+use connect4::solver::{Progress, SearchStats, Solver, SolverOptions};
 use connect4::transposition_table::TranspositionTable;
 
+const PROGRAM: &str = "connect4";
+
+const ABOUT: &str = "A connect 4 solver, exits with 0 on success, 1 when a command fails and 2 on a bad command line";
+
+const GLOBAL_FLAGS: &[Flag] = &[
+    Flag { name: "size", value: Some("WIDTHxHEIGHT"), help: "board size, 7x6 by default" },
+    Flag { name: "hash", value: Some("MEMORY"), help: "transposition table memory such as 64MB or 1GB" },
+    Flag { name: "threads", value: Some("N"), help: "search threads, 1 by default" },
+    Flag { name: "weak", value: None, help: "only solve for win, draw or loss" },
+    Flag { name: "book", value: Some("PATH"), help: "opening book to use, can be given several times" },
+    Flag { name: "no-book", value: None, help: "solve without an opening book" },
+    Flag { name: "stats", value: None, help: "print search statistics to stderr" },
+];
+
+const FORMAT_FLAG: Flag = Flag { name: "format", value: Some("text|json"), help: "output format, text by default" };
+
+const TABLE_FLAG: Flag = Flag { name: "table", value: Some("PATH"), help: "load the transposition table from PATH and save it back when done" };
+
+const COMMANDS: &[Command] = &[
+    Command { name: "solve", arguments: &["position"], about: "Prints the score of a position", flags: &[FORMAT_FLAG] },
+    Command { name: "best_move", arguments: &["position"], about: "Prints the best column to play", flags: &[FORMAT_FLAG] },
+    Command { name: "pv", arguments: &["position"], about: "Prints the score and principal variation of a position", flags: &[FORMAT_FLAG] },
+    Command { name: "analyze", arguments: &["position"], about: "Prints the score of every column, _ for full ones", flags: &[FORMAT_FLAG] },
+    Command { name: "stringify", arguments: &["position"], about: "Draws a position", flags: &[] },
+    Command { name: "create_position", arguments: &["depth"], about: "Writes every position at depth to ./data/<depth>_positions", flags: &[] },
+    Command {
+        name: "work",
+        arguments: &["depth", "skip", "take"],
+        about: "Solves take positions of ./data/<depth>_positions after the first skip, resuming a previous run",
+        flags: &[TABLE_FLAG],
+    },
+    Command { name: "work_all", arguments: &["depth"], about: "Solves every position of ./data/<depth>_positions, resuming a previous run", flags: &[TABLE_FLAG] },
+    Command { name: "create_book", arguments: &["solved-file"], about: "Adds a solved file or zip archive of them to the opening book", flags: &[] },
+    Command {
+        name: "build-book",
+        arguments: &[],
+        about: "Solves every position at a depth into a new opening book, resuming an interrupted build",
+        flags: &[Flag { name: "depth", value: Some("N"), help: "depth of the positions in the book, required" }],
+    },
+    Command { name: "utilization", arguments: &[], about: "Prints how many positions each opening book holds", flags: &[] },
+    Command {
+        name: "bench",
+        arguments: &["test-file"],
+        about: "Solves a test set and prints accuracy, time and nodes",
+        flags: &[Flag { name: "limit", value: Some("N"), help: "only solve the first N positions" }, FORMAT_FLAG],
+    },
+    Command {
+        name: "match",
+        arguments: &[],
+        about: "Plays two engines against each other",
+        flags: &[
+            Flag { name: "games", value: Some("N"), help: "games to play, 2 by default" },
+            Flag { name: "openings", value: Some("DEPTH"), help: "start games from every position at DEPTH instead of the empty board" },
+            Flag { name: "first", value: Some("SPEC"), help: "the first engine, such as level=easy,weak,hash=16MB,seed=3" },
+            Flag { name: "second", value: Some("SPEC"), help: "the second engine" },
+            FORMAT_FLAG,
+        ],
+    },
+    Command {
+        name: "play",
+        arguments: &[],
+        about: "Plays against the engine in the terminal",
+        flags: &[
+            Flag { name: "second", value: None, help: "let the engine move first" },
+            Flag { name: "level", value: Some("LEVEL"), help: "easy, medium, hard or perfect, perfect by default" },
+            Flag { name: "seed", value: Some("N"), help: "seed for the engine's choices, the same seed plays the same game" },
+        ],
+    },
+];
+
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    match args[1].as_str() {
-        "solve" => solve(args[2].as_str()),
-        "create_position" => create_position(args[2].parse::<u8>().unwrap()),
-        "work" => work(
-            args[2].parse::<u8>().unwrap(),
-            Some((args[3].parse::<usize>().unwrap(), args[4].parse::<usize>().unwrap())),
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        if let CliError::Usage(_) = error {
+            eprintln!("Run '{} --help' or '{} <command> --help' for usage", PROGRAM, PROGRAM);
+        }
+        process::exit(error.exit_code());
+    }
+}
+
+fn run(args: &[String]) -> Result<(), CliError> {
+    let args = Args::parse(args, COMMANDS, GLOBAL_FLAGS)?;
+    let Some(command) = args.command() else {
+        if args.help() {
+            print!("{}", cli::overview(PROGRAM, ABOUT, COMMANDS, GLOBAL_FLAGS));
+            return Ok(());
+        }
+        return Err(CliError::Usage("No command given".to_string()));
+    };
+    if args.help() {
+        print!("{}", command.help(PROGRAM, GLOBAL_FLAGS));
+        return Ok(());
+    }
+
+    let options = solver_options(&args)?;
+    if options.dimensions.fits::<u64>() {
+        run_command::<u64>(command, &args, options)
+    }
+    else {
+        run_command::<u128>(command, &args, options)
+    }
+}
+
+fn solver_options(args: &Args) -> Result<SolverOptions, CliError> {
+    let table_memory = match args.value("hash") {
+        Some(memory) => transposition_table::parse_memory(memory)
+            .map_err(|error| CliError::Usage(format!("Invalid --hash: {}", error)))?,
+        None => TranspositionTable::DEFAULT_MEMORY,
+    };
+    let mut opening_books = None;
+    for book in args.values("book") {
+        opening_books.get_or_insert_with(Vec::new).push(PathBuf::from(book));
+    }
+    if args.switch("no-book") {
+        opening_books = Some(Vec::new());
+    }
+    Ok(SolverOptions {
+        dimensions: args.value_as::<Dimensions>("size")?.unwrap_or(Dimensions::STANDARD),
+        threads: args.value_as::<usize>("threads")?.unwrap_or(1).max(1),
+        table_memory,
+        weak: args.switch("weak"),
+        opening_books,
+    })
+}
+
+fn run_command<B: BoardBits>(command: &Command, args: &Args, options: SolverOptions) -> Result<(), CliError> {
+    let dimensions = options.dimensions;
+    let show_stats = args.switch("stats");
+    let format = args.value_as::<OutputFormat>("format")?.unwrap_or_default();
+    match command.name {
+        "solve" => solve::<B>(args.positional(0)?, options, format, show_stats)?,
+        "create_position" => create_position::<B>(args.positional_as::<u8>(0)?, dimensions)?,
+        "work" => work::<B>(
+            args.positional_as::<u8>(0)?,
+            Some((args.positional_as::<usize>(1)?, args.positional_as::<usize>(2)?)),
+            options,
+            args.value("table"),
+            show_stats,
+        )?,
+        "work_all" => work::<B>(args.positional_as::<u8>(0)?, None, options, args.value("table"), show_stats)?,
+        "create_book" => create_book::<B>(args.positional(0)?, &options)?,
+        "build-book" => match args.value_as::<u8>("depth")? {
+            Some(depth) => build_book::<B>(depth, &options)?,
+            None => return Err(CliError::Usage("build-book needs --depth N".to_string())),
+        },
+        "utilization" => opening_book_utilization(&options),
+        "best_move" => best_move::<B>(args.positional(0)?, options, format)?,
+        "pv" => principal_variation::<B>(args.positional(0)?, options, format)?,
+        "analyze" => analyze::<B>(args.positional(0)?, options, format)?,
+        "stringify" => stringify::<B>(args.positional(0)?, dimensions)?,
+        "bench" => bench::<B>(args.positional(0)?, args.value_as::<usize>("limit")?.unwrap_or(usize::MAX), options, format)?,
+        "match" => {
+            let games = args.value_as::<usize>("games")?.unwrap_or(2);
+            let depth = args.value_as::<u8>("openings")?;
+            let first = engine_config(args.value("first").unwrap_or(""), &options)
+                .map_err(|error| CliError::Usage(format!("Invalid --first: {}", error)))?;
+            let second = engine_config(args.value("second").unwrap_or(""), &options)
+                .map_err(|error| CliError::Usage(format!("Invalid --second: {}", error)))?;
+            run_match::<B>(games, depth, [first, second], dimensions, format)?
+        },
+        "play" => {
+            let human = if args.switch("second") { Player::Two } else { Player::One };
+            let strength = args.value_as::<Strength>("level")?.unwrap_or(Strength::PERFECT);
+            let seed = match args.value_as::<u64>("seed")? {
+                Some(seed) => seed,
+                None => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64,
+            };
+            play::<B>(human, Engine::new(new_solver(&options), strength, seed), dimensions)
+        },
+        name => unreachable!("{} has no implementation", name),
+    }
+    Ok(())
+}
+
+
+fn stringify<B: BoardBits>(position_str: &str, dimensions: Dimensions) -> Result<(), String> {
+    println!("{}", position_factory::parse_with_dimensions::<B>(position_str, dimensions)?.stringify());
+    Ok(())
+}
+
+// the metrics of the gamesolver.org test sets, weak solves only need the sign of the score right
+fn bench<B: BoardBits>(test_filepath: &str, limit: usize, options: SolverOptions, format: OutputFormat) -> Result<(), String> {
+    let mut solver = new_solver(&options);
+    let dimensions = options.dimensions;
+    let mut total_stats = SearchStats::default();
+    let mut positions = 0;
+    let mut correct = 0;
+
+    for (index, line_result) in reader::open_positions(test_filepath)?.take(limit).enumerate() {
+        let line = line_result.map_err(|error| format!("{}:{}: {}", test_filepath, index + 1, error))?;
+        let parsed = reader::parse_position_score(line.as_str()).and_then(|(position_string, expected)|
+            Ok((position_factory::create_with_dimensions::<B>(position_string, dimensions)?, expected))
+        );
+        let (position, expected) = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                eprintln!("{}:{}: {}", test_filepath, index + 1, error);
+                continue;
+            },
+        };
+        let score = solver.solve(position);
+        total_stats += solver.stats();
+        positions += 1;
+        if score == expected || options.weak && score.signum() == expected.signum() {
+            correct += 1;
+        }
+    }
+
+    let positions_f64 = positions.max(1) as f64;
+    let accuracy = 100.0 * correct as f64 / positions_f64;
+    let mean_time = total_stats.elapsed.as_secs_f64() / positions_f64;
+    let mean_nodes = total_stats.nodes as f64 / positions_f64;
+    match format {
+        OutputFormat::Text => {
+            println!("positions: {}", positions);
+            println!("accuracy: {:.2}%", accuracy);
+            println!("mean time: {:.6}s", mean_time);
+            println!("mean nodes: {:.1}", mean_nodes);
+            println!("nodes/s: {:.0}", total_stats.nodes_per_second());
+        },
+        OutputFormat::Json => println!(
+            "{{\"positions\":{},\"accuracy\":{:.2},\"mean_time\":{:.6},\"mean_nodes\":{:.1},\"nodes_per_second\":{:.0}}}",
+            positions, accuracy, mean_time, mean_nodes, total_stats.nodes_per_second(),
         ),
-        "work_all" => work(args[2].parse::<u8>().unwrap(), None),
-        "create_book" => create_book(args[2].as_str()),
-        "utilization" => opening_book_utilization(),
-        "best_move" => best_move(args[2].as_str()),
-        "analyze" => analyze(args[2].as_str()),
-        "stringify" => stringify(args[2].as_str()),
-        _ => println!("Command Not Recognized"),
     }
+    Ok(())
 }
 
-fn stringify(position_str: &str) {
-    println!("{}", position_factory::create(position_str).unwrap().stringify());
+// a comma separated list such as "level=easy,weak,hash=16MB,seed=3", anything left out comes from options
+fn engine_config(spec: &str, options: &SolverOptions) -> Result<(SolverOptions, Strength, u64), String> {
+    let mut options = options.clone();
+    let mut strength = Strength::PERFECT;
+    let mut seed = 0;
+    for setting in spec.split(',').filter(|setting| !setting.is_empty()) {
+        match setting.split_once('=') {
+            None if setting == "weak" => options.weak = true,
+            Some(("level", level)) => strength = level.parse::<Strength>()?,
+            Some(("hash", memory)) => options.table_memory = transposition_table::parse_memory(memory)?,
+            Some(("seed", value)) => seed = value.parse::<u64>().map_err(|_| format!("'{}' is not a seed", value))?,
+            _ => return Err(format!("'{}' is not an engine setting", setting)),
+        }
+    }
+    Ok((options, strength, seed))
+}
+
+// openings are every position at depth, otherwise games start from the empty board
+fn run_match<B: BoardBits>(
+    games: usize,
+    depth: Option<u8>,
+    configs: [(SolverOptions, Strength, u64); 2],
+    dimensions: Dimensions,
+    format: OutputFormat,
+) -> Result<(), String> {
+    let empty = position_factory::create_with_dimensions::<B>("", dimensions)?;
+    let openings = match depth {
+        Some(depth) => {
+            let mut explorer = Explorer::<B>::new();
+            explorer.explore(empty, String::new(), depth);
+            // games are won before the board fills, so deep enough there are no positions left
+            if explorer.output.is_empty() {
+                return Err(format!("There are no openings at depth {}", depth));
+            }
+            explorer.output.iter()
+                .map(|opening| position_factory::create_with_dimensions::<B>(opening, dimensions))
+                .collect::<Result<Vec<_>, String>>()?
+        },
+        None => vec![empty],
+    };
+    let [mut first, mut second] = configs.map(|(options, strength, seed)| Engine::new(new_solver(&options), strength, seed));
+    let stats = engine::play_match([&mut first, &mut second], &openings, games);
+    match format {
+        OutputFormat::Text => println!("{}", stats),
+        OutputFormat::Json => {
+            let [first_time, second_time] = stats.time_per_move().map(|time| time.as_secs_f64() * 1000.0);
+            println!(
+                "{{\"wins\":{},\"draws\":{},\"losses\":{},\"time_per_move_ms\":[{:.3},{:.3}]}}",
+                stats.wins, stats.draws, stats.losses, first_time, second_time,
+            );
+        },
+    }
+    Ok(())
 }
 
-fn analyze(position_str: &str) {
-    let mut solver = Solver::new(None);
-    let position = position_factory::create(position_str).unwrap();
-    println!("{:?}", solver.analyze(position).iter().map(|col|
-        if col.is_some() {
-            col.unwrap().to_string()
+// the human moves by typing a column, u takes back their last move and q quits
+fn play<B: BoardBits>(human: Player, mut engine: Engine, dimensions: Dimensions) {
+    let mut game = Game::<B>::with_dimensions(dimensions);
+    let mut input = io::stdin().lock().lines();
+    let columns = (0..dimensions.width).map(|col| col.to_string()).collect::<Vec<_>>().join(" ");
+
+    loop {
+        println!("{}{}", game.stringify(), columns);
+        let result = match game.status() {
+            GameStatus::Win { player, .. } if player == human => Some("You win!"),
+            GameStatus::Win { .. } => Some("The engine wins"),
+            GameStatus::Draw => Some("Draw"),
+            GameStatus::Ongoing => None,
+        };
+
+        if result.is_none() && Player::to_move(game.get_move_count()) != human {
+            let col = engine.choose_move(game.clone()).unwrap();
+            println!("The engine plays {}", col);
+            game.play(col);
+            continue;
         }
-        else {
-            "_".to_string()
+
+        loop {
+            match result {
+                Some(result) => print!("{} (u to undo, q to quit): ", result),
+                None => print!("Your move, player {} (0-{}, u to undo, q to quit): ", human, dimensions.width - 1),
+            }
+            io::stdout().flush().unwrap();
+            let Some(Ok(line)) = input.next() else {
+                println!();
+                return;
+            };
+            match line.trim() {
+                "q" => return,
+                "u" => {
+                    if take_back_turn(&mut game, human) {
+                        break;
+                    }
+                    println!("There is no move of yours to take back");
+                },
+                _ if result.is_some() => {},
+                input => match input.parse::<u8>() {
+                    Ok(col) if col < dimensions.width && game.can_play(col) => {
+                        game.play(col);
+                        break;
+                    },
+                    _ => println!("{} is not a playable column", input),
+                },
+            }
+        }
+    }
+}
+
+// takes back moves up to and including the human's last one, so that it is their turn again
+fn take_back_turn<B: BoardBits>(game: &mut Game<B>, human: Player) -> bool {
+    let last_human_move = (0..game.moves().len()).rev()
+        .find(|ply| Player::to_move(*ply as u8) == human);
+    match last_human_move {
+        Some(ply) => {
+            while game.moves().len() > ply {
+                game.take_back();
+            }
+            true
+        },
+        None => false,
+    }
+}
+
+fn analyze<B: BoardBits>(position_str: &str, options: SolverOptions, format: OutputFormat) -> Result<(), String> {
+    let mut solver = new_solver(&options);
+    let dimensions = options.dimensions;
+    let position = position_factory::create_with_dimensions::<B>(position_str, dimensions)?;
+    let scores = solver.analyze(position);
+    match format {
+        OutputFormat::Text => println!("{:?}", scores.iter().map(|col|
+            match col {
+                Some(score) => score.to_string(),
+                None => "_".to_string(),
+            }
+        ).collect::<Vec<String>>()),
+        OutputFormat::Json => println!("{{\"position\":\"{}\",\"scores\":[{}]}}", position_str, scores.iter().map(|col|
+            match col {
+                Some(score) => score.to_string(),
+                None => "null".to_string(),
+            }
+        ).collect::<Vec<String>>().join(",")),
+    }
+    Ok(())
+}
+
+// prints 0 when no column can be played, or null in json
+fn best_move<B: BoardBits>(position_str: &str, options: SolverOptions, format: OutputFormat) -> Result<(), String> {
+    let mut solver = new_solver(&options);
+    let dimensions = options.dimensions;
+    let position = position_factory::create_with_dimensions::<B>(position_str, dimensions)?;
+    let best_move = solver.best_move(position);
+    match format {
+        OutputFormat::Text => println!("{}", best_move.unwrap_or(0)),
+        OutputFormat::Json => println!(
+            "{{\"position\":\"{}\",\"best_move\":{}}}",
+            position_str, best_move.map_or("null".to_string(), |col| col.to_string()),
+        ),
+    }
+    Ok(())
+}
+
+fn principal_variation<B: BoardBits>(position_str: &str, options: SolverOptions, format: OutputFormat) -> Result<(), String> {
+    let mut solver = new_solver(&options);
+    let dimensions = options.dimensions;
+    let position = position_factory::create_with_dimensions::<B>(position_str, dimensions)?;
+    let (score, principal_variation) = solver.solve_with_pv(position);
+    let moves = principal_variation.iter()
+        .map(|col| (b'0' + col) as char)
+        .collect::<String>();
+    match format {
+        OutputFormat::Text => println!("{} {}", score, moves),
+        OutputFormat::Json => println!("{{\"position\":\"{}\",\"score\":{},\"pv\":\"{}\"}}", position_str, score, moves),
+    }
+    Ok(())
+}
+
+fn new_solver(options: &SolverOptions) -> Solver {
+    let solver = Solver::new(Some(options.clone()));
+    // the default book is optional, only a book asked for by --book or the environment is missed
+    let configured = options.opening_books.is_some() || env::var_os(opening_book::BOOK_PATH_VARIABLE).is_some();
+    for error in solver.opening_book_errors() {
+        if configured || !matches!(error, BookError::Missing(_)) {
+            eprintln!("{}, solving without it", error);
         }
-    ).collect::<Vec<String>>());
+    }
+    solver
 }
 
-fn best_move(position_str: &str) {
-    let mut solver = Solver::new(None);
-    let position = position_factory::create(position_str).unwrap();
-    println!("{}", solver.best_move(position).unwrap_or(0));
+// --book can be given several times, the books are consulted in order
+fn opening_book_filepaths(options: &SolverOptions) -> Vec<PathBuf> {
+    options.opening_books.clone()
+        .unwrap_or_else(|| opening_book::default_filepaths(options.dimensions))
 }
 
-fn opening_book_utilization() {
-    let (used, total) = get_opening_book().utilization();
-    println!("{}/{} = {}%", used, total, 100 * used / total);
+fn opening_book_utilization(options: &SolverOptions) {
+    for filepath in opening_book_filepaths(options) {
+        match load_opening_book(&filepath, options.dimensions) {
+            Ok(book) => println!("{}: {} positions", filepath.display(), book.len()),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
 }
 
-fn create_book(solved_filepath: &str) {
-    let mut book: TranspositionTable = get_opening_book();
-    for line_result in reader::read_positions(solved_filepath) {
-        let (position_string, score) = reader::line_to_position_score(line_result.unwrap());
-        let position = position_factory::create(position_string.as_str()).unwrap();
-        book.put(position.symmetric_key(), score);
+// adds to the first configured book, if it exists
+fn create_book<B: BoardBits>(solved_filepath: &str, options: &SolverOptions) -> Result<(), String> {
+    let dimensions = options.dimensions;
+    let Some(book_filepath) = opening_book_filepaths(options).into_iter().next() else {
+        return Err("No opening book path to write to".to_string());
+    };
+    let mut book = match load_opening_book(&book_filepath, dimensions) {
+        Ok(book) => book,
+        Err(BookError::Missing(_)) => OpeningBook::new(dimensions),
+        Err(error) => return Err(error.to_string()),
+    };
+    // the entries of every file are merged into the book at once, merging each file would copy the book every time
+    let mut entries: Vec<(B, i8)> = Vec::new();
+    let mut malformed = 0;
+    let result = reader::read_solved_files(solved_filepath, |name, contents| {
+        let file_entries = BufRead::lines(contents)
+            .enumerate()
+            .map_while(|(index, line_result)| match line_result {
+                Ok(line) => Some((index + 1, line)),
+                Err(error) => {
+                    eprintln!("{}:{}: {}", name, index + 1, error);
+                    None
+                },
+            })
+            .filter_map(|(line_number, line)| {
+                let entry = reader::parse_position_score(line.as_str()).and_then(|(position_string, score)| {
+                    let position = position_factory::create_with_dimensions::<B>(position_string, dimensions)?;
+                    Ok((position.symmetric_key(), score))
+                });
+                if let Err(error) = &entry {
+                    eprintln!("{}:{}: {}", name, line_number, error);
+                    malformed += 1;
+                }
+                entry.ok()
+            });
+        entries.extend(file_entries);
+    });
+    result?;
+    let report = book.extend(entries);
+    println!("stored {} positions, dropped {} already in the book, skipped {} malformed lines", report.stored, report.dropped, malformed);
+    save_opening_book(&book, &book_filepath)
+        .map_err(|error| format!("Could not save the opening book: {}", error))
+}
+
+// enumerates the positions at depth, solves them on options.threads workers and writes the book
+// solved positions are appended to a checkpoint next to the book, so an interrupted build resumes where it stopped
+fn build_book<B: BoardBits>(depth: u8, options: &SolverOptions) -> Result<(), String> {
+    let dimensions = options.dimensions;
+    let Some(book_filepath) = opening_book_filepaths(options).into_iter().next() else {
+        return Err("No opening book path to write to".to_string());
+    };
+    let checkpoint_filepath = PathBuf::from(format!("{}.checkpoint", book_filepath.display()));
+
+    let mut explorer = Explorer::<B>::new();
+    explorer.explore(position_factory::create_with_dimensions::<B>("", dimensions)?, String::new(), depth);
+
+    let mut checkpoint = fs::OpenOptions::new().create(true).read(true).write(true).truncate(false).open(&checkpoint_filepath)
+        .map_err(|error| format!("Could not open {}: {}", checkpoint_filepath.display(), error))?;
+    let checkpointed = reader::resume_checkpoint(&mut checkpoint)
+        .map_err(|error| format!("Could not resume {}: {}", checkpoint_filepath.display(), error))?;
+    let mut solved = HashMap::new();
+    let mut pending = Vec::new();
+    for position_string in explorer.output {
+        match checkpointed.get(&position_string) {
+            Some(score) => {
+                solved.insert(position_string, *score);
+            },
+            None => pending.push(position_string),
+        }
     }
-    book.put(0, 1);
-    let _ = save_opening_book(book);
+    eprintln!("{} positions at depth {}, {} already solved", pending.len() + solved.len(), depth, solved.len());
+
+    // the workers split the table memory, and solve without a book since the book is the one being rebuilt
+    let worker_options = SolverOptions {
+        threads: 1,
+        table_memory: options.table_memory / options.threads,
+        opening_books: Some(Vec::new()),
+        ..options.clone()
+    };
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| -> Result<(), String> {
+        for _ in 0..options.threads {
+            let sender = sender.clone();
+            let (next, pending, worker_options) = (&next, &pending, &worker_options);
+            scope.spawn(move || {
+                let mut solver = Solver::new(Some(worker_options.clone()));
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(position_string) = pending.get(index) else {
+                        break;
+                    };
+                    let score = position_factory::create_with_dimensions::<B>(position_string, dimensions)
+                        .map(|position| solver.solve(position));
+                    // the receiver is gone once writing the checkpoint failed
+                    if sender.send((index, score)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (count, (index, score)) in receiver.into_iter().enumerate() {
+            let written = score.and_then(|score| {
+                checkpoint.write_all(format!("{} {}\n", pending[index], score).as_bytes())
+                    .and_then(|()| checkpoint.flush())
+                    .map_err(|error| format!("Could not write {}: {}", checkpoint_filepath.display(), error))?;
+                solved.insert(pending[index].clone(), score);
+                Ok(())
+            });
+            if let Err(error) = written {
+                // no worker starts another position, and those still solving stop once they find the receiver gone
+                next.store(pending.len(), Ordering::Relaxed);
+                return Err(error);
+            }
+            if (count + 1) % 1000 == 0 {
+                eprintln!("{}/{} solved", count + 1, pending.len());
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut book = OpeningBook::new(dimensions);
+    let entries = solved.iter()
+        .map(|(position_string, score)| {
+            let position = position_factory::create_with_dimensions::<B>(position_string, dimensions)?;
+            Ok((position.symmetric_key(), *score))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let report = book.extend(entries);
+    println!("stored {} positions, dropped {} already in the book", report.stored, report.dropped);
+    save_opening_book(&book, &book_filepath)
+        .map_err(|error| format!("Could not save the opening book: {}", error))?;
+    let _ = fs::remove_file(&checkpoint_filepath);
+    Ok(())
 }
 
-fn solve(pos: &str) {
-    let mut solver = Solver::new(None);
-    let position = position_factory::create(pos).unwrap();
-    println!("{}", solver.solve(position));
+fn solve<B: BoardBits>(pos: &str, options: SolverOptions, format: OutputFormat, show_stats: bool) -> Result<(), String> {
+    let mut solver = new_solver(&options);
+    let dimensions = options.dimensions;
+    if show_stats {
+        solver.set_progress_callback(Some(Box::new(|progress: &Progress|
+            eprintln!("[{}, {}] {}", progress.min, progress.max, progress.stats)
+        )));
+    }
+    let position = position_factory::create_with_dimensions::<B>(pos, dimensions)?;
+    let score = solver.solve(position);
+    match format {
+        OutputFormat::Text => println!("{}", score),
+        OutputFormat::Json => println!("{{\"position\":\"{}\",\"score\":{}}}", pos, score),
+    }
+    if show_stats {
+        eprintln!("{}", solver.stats());
+    }
+    Ok(())
 }
 
-fn create_position(depth: u8) {
-    let position = position_factory::create("").unwrap();
+fn create_position<B: BoardBits>(depth: u8, dimensions: Dimensions) -> Result<(), String> {
+    let position = position_factory::create_with_dimensions::<B>("", dimensions)?;
     let mut explorer = Explorer::new();
     explorer.explore(position, String::new(), depth);
 
     let filepath = format!("./data/{}_positions", depth);
-    let mut file = File::create(filepath.as_str()).unwrap();
-    file.write_all(explorer.output.join("\n").as_bytes()).unwrap();
+    fs::write(filepath.as_str(), explorer.output.join("\n"))
+        .map_err(|error| format!("Could not write {}: {}", filepath, error))
 }
 
-fn work(depth: u8, skip_take: Option<(usize, usize)>) {
-    let mut solver = Solver::new(None);
+// with a table file, the transposition table is carried over from the previous batch and saved for the next one
+fn work<B: BoardBits>(depth: u8, skip_take: Option<(usize, usize)>, options: SolverOptions, table_filepath: Option<&str>, show_stats: bool) -> Result<(), String> {
+    let mut solver = new_solver(&options);
+    if let Some(table_filepath) = table_filepath {
+        if Path::new(table_filepath).exists() {
+            if let Err(error) = solver.load_table(table_filepath) {
+                eprintln!("{}, starting with an empty table", error);
+            }
+        }
+    }
+    let dimensions = options.dimensions;
+    let mut total_stats = SearchStats::default();
 
     let (skip, take) = skip_take.unwrap_or((0, usize::MAX));
 
@@ -95,18 +640,43 @@ fn work(depth: u8, skip_take: Option<(usize, usize)>) {
     else {
         format!("./data/{}_positions_solved", depth)
     };
-    let mut file = File::create(write_filepath.as_str()).unwrap();
-
+    let progress_filepath = format!("{}.progress", write_filepath);
 
-    let results = reader::read_positions(read_filepath.as_str());
-    let filtered_results = results
+    let positions = reader::open_positions(read_filepath.as_str())?
         .skip(skip)
-        .take(take);
+        .take(take)
+        .collect::<Result<Vec<String>, io::Error>>()
+        .map_err(|error| format!("Could not read {}: {}", read_filepath, error))?;
 
-    for line_result in filtered_results {
-        let line = line_result.unwrap();
-        let position = position_factory::create(line.as_str()).unwrap();
+    let mut file = fs::OpenOptions::new().create(true).read(true).write(true).truncate(false).open(write_filepath.as_str())
+        .map_err(|error| format!("Could not open {}: {}", write_filepath, error))?;
+    let solved = reader::resume_solved(&mut file, &positions)
+        .map_err(|error| format!("Could not resume {}: {}", write_filepath, error))?;
+    if solved > 0 {
+        eprintln!("{} of {} positions already solved in {}", solved, positions.len(), write_filepath);
+    }
+
+    for (index, line) in positions.iter().enumerate().skip(solved) {
+        let position = position_factory::create_with_dimensions::<B>(line.as_str(), dimensions)
+            .map_err(|error| format!("{}:{}: {}", read_filepath, skip + index + 1, error))?;
         let score = solver.solve(position);
-        file.write_all(format!("{} {}\n", line, score).as_bytes()).unwrap();
+        total_stats += solver.stats();
+        file.write_all(format!("{} {}\n", line, score).as_bytes())
+            .and_then(|()| file.flush())
+            .map_err(|error| format!("Could not write {}: {}", write_filepath, error))?;
+        fs::write(progress_filepath.as_str(), format!("{}/{}\n", index + 1, positions.len()))
+            .map_err(|error| format!("Could not write {}: {}", progress_filepath, error))?;
     }
-}
\ No newline at end of file
+    let _ = fs::remove_file(progress_filepath.as_str());
+
+    if let Some(table_filepath) = table_filepath {
+        if let Err(error) = solver.save_table(table_filepath) {
+            eprintln!("Could not save the table to {}: {}", table_filepath, error);
+        }
+    }
+
+    if show_stats {
+        eprintln!("{}", total_stats);
+    }
+    Ok(())
+}
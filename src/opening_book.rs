@@ -1,49 +1,275 @@
 use std::collections::HashSet;
+use std::env;
 use std::fs;
-use std::io::Write;
-use bincode::config;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use bincode::{config, Decode, Encode};
 use bincode::config::Configuration;
-use crate::position::{Position, WIDTH, HEIGHT};
-use crate::transposition_table::TranspositionTable;
+use crate::position::{Position, Dimensions, BoardBits};
 
-pub struct Explorer {
-    visited: HashSet<u64>,
+// exact scores of positions, looked up by symmetric key
+// keys are kept sorted next to their scores, so every stored position is found and nothing else is
+pub struct OpeningBook {
+    dimensions: Dimensions,
+    keys: Keys,
+    scores: Vec<i8>,
+}
+
+#[derive(Encode, Decode)]
+enum Keys {
+    Narrow(Vec<u64>),
+    Wide(Vec<u128>),
+}
+
+// inputs are dropped when their position is already in the book
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BookReport {
+    pub stored: usize,
+    pub dropped: usize,
+}
+
+impl std::ops::AddAssign for BookReport {
+    fn add_assign(&mut self, other: Self) {
+        self.stored += other.stored;
+        self.dropped += other.dropped;
+    }
+}
+
+pub struct Explorer<B: BoardBits = u64> {
+    visited: HashSet<B>,
     pub output: Vec<String>,
 }
 
-pub fn get_opening_book() -> TranspositionTable {
-    return match &fs::read(filepath().as_str()) {
-        Ok(encoded) => {
-            match bincode::decode_from_slice(&encoded[..], bincode_config()) {
-                Ok((transposition_table, _)) => {
-                    transposition_table
-                },
-                Err(_) => TranspositionTable::new(),
-            }
+#[derive(Debug)]
+pub enum BookError {
+    Missing(String),
+    Io(String, std::io::Error),
+    NotABook(String),
+    UnsupportedVersion { filepath: String, version: u16 },
+    WrongDimensions { filepath: String, expected: Dimensions, found: (u8, u8) },
+    ChecksumMismatch(String),
+    Corrupt(String, String),
+    WrongEntryCount { filepath: String, expected: u64, found: u64 },
+}
+
+impl std::fmt::Display for BookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookError::Missing(filepath) => write!(f, "No opening book at {}", filepath),
+            BookError::Io(filepath, error) => write!(f, "Could not read the opening book {}: {}", filepath, error),
+            BookError::NotABook(filepath) => write!(f, "{} is not an opening book", filepath),
+            BookError::UnsupportedVersion { filepath, version } =>
+                write!(f, "{} has format version {}, only version {} is supported", filepath, version, BookHeader::VERSION),
+            BookError::WrongDimensions { filepath, expected, found: (width, height) } =>
+                write!(f, "{} is a book for {}x{} boards, not {}", filepath, width, height, expected),
+            BookError::ChecksumMismatch(filepath) => write!(f, "{} is corrupt, its checksum does not match", filepath),
+            BookError::Corrupt(filepath, reason) => write!(f, "{} is corrupt: {}", filepath, reason),
+            BookError::WrongEntryCount { filepath, expected, found } =>
+                write!(f, "{} should hold {} entries but holds {}", filepath, expected, found),
         }
-        Err(_) => TranspositionTable::new(),
-    };
+    }
+}
+
+impl std::error::Error for BookError {}
+
+// written before the encoded book, the checksum covers everything after the header
+#[derive(Encode, Decode)]
+struct BookHeader {
+    magic: [u8; 4],
+    version: u16,
+    width: u8,
+    height: u8,
+    entries: u64,
+    checksum: u32,
+}
+
+impl BookHeader {
+    const MAGIC: [u8; 4] = *b"C4OB";
+
+    const VERSION: u16 = 2;
 }
 
-pub fn save_opening_book(opening_book: TranspositionTable) -> std::io::Result<()> {
-    let encoded: Vec<u8> = bincode::encode_to_vec(opening_book, bincode_config()).unwrap();
+// a list of paths in the environment variable replaces the default book, an empty value means no book
+pub const BOOK_PATH_VARIABLE: &str = "CONNECT4_OPENING_BOOK";
+
+pub fn default_filepaths(dimensions: Dimensions) -> Vec<PathBuf> {
+    match env::var_os(BOOK_PATH_VARIABLE) {
+        Some(paths) => env::split_paths(&paths)
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect(),
+        None => vec![PathBuf::from(format!("./data/{}_opening_book", dimensions))],
+    }
+}
+
+// books that were never created are reported as Missing, so callers can choose to go on without one
+pub fn load_opening_book(path: &Path, dimensions: Dimensions) -> Result<OpeningBook, BookError> {
+    let filepath = path.display().to_string();
+    let encoded = fs::read(path).map_err(|error| match error.kind() {
+        ErrorKind::NotFound => BookError::Missing(filepath.clone()),
+        _ => BookError::Io(filepath.clone(), error),
+    })?;
+    let (header, header_length): (BookHeader, usize) = bincode::decode_from_slice(&encoded[..], bincode_config())
+        .map_err(|_| BookError::NotABook(filepath.clone()))?;
+    if header.magic != BookHeader::MAGIC {
+        return Err(BookError::NotABook(filepath));
+    }
+    if header.version != BookHeader::VERSION {
+        return Err(BookError::UnsupportedVersion { filepath, version: header.version });
+    }
+    if (header.width, header.height) != (dimensions.width, dimensions.height) {
+        return Err(BookError::WrongDimensions { filepath, expected: dimensions, found: (header.width, header.height) });
+    }
+    let payload = &encoded[header_length..];
+    if crc32(payload) != header.checksum {
+        return Err(BookError::ChecksumMismatch(filepath));
+    }
+    let ((keys, scores), _): ((Keys, Vec<i8>), usize) = bincode::decode_from_slice(payload, bincode_config())
+        .map_err(|error| BookError::Corrupt(filepath.clone(), error.to_string()))?;
+    let opening_book = OpeningBook { dimensions, keys, scores };
+    if opening_book.len() as u64 != header.entries {
+        return Err(BookError::WrongEntryCount { filepath, expected: header.entries, found: opening_book.len() as u64 });
+    }
+    if opening_book.scores.len() != opening_book.len() || !opening_book.is_sorted() {
+        return Err(BookError::Corrupt(filepath, "positions are not stored in order".to_string()));
+    }
+    Ok(opening_book)
+}
+
+pub fn save_opening_book(opening_book: &OpeningBook, path: &Path) -> std::io::Result<()> {
+    let payload: Vec<u8> = bincode::encode_to_vec((&opening_book.keys, &opening_book.scores), bincode_config())
+        .map_err(std::io::Error::other)?;
+    let header = BookHeader {
+        magic: BookHeader::MAGIC,
+        version: BookHeader::VERSION,
+        width: opening_book.dimensions.width,
+        height: opening_book.dimensions.height,
+        entries: opening_book.len() as u64,
+        checksum: crc32(&payload),
+    };
+    let encoded_header = bincode::encode_to_vec(&header, bincode_config()).map_err(std::io::Error::other)?;
 
     let mut file = fs::OpenOptions::new()
         .create(true)
         .write(true)
-        .open(filepath().as_str())?;
-    file.write_all(&*encoded)
-}
-
-fn filepath() -> String {
-    format!("./data/{}x{}_opening_book", WIDTH, HEIGHT)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(&encoded_header)?;
+    file.write_all(&payload)
 }
 
 fn bincode_config() -> Configuration {
     config::standard()
 }
 
-impl Explorer {
+// CRC-32 as used by zip and png
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl OpeningBook {
+    pub fn new(dimensions: Dimensions) -> Self {
+        let keys = if dimensions.fits::<u64>() {
+            Keys::Narrow(Vec::new())
+        }
+        else {
+            Keys::Wide(Vec::new())
+        };
+        Self {
+            dimensions,
+            keys,
+            scores: Vec::new(),
+        }
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.keys {
+            Keys::Narrow(keys) => keys.len(),
+            Keys::Wide(keys) => keys.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get<B: BoardBits>(&self, key: B) -> Option<i8> {
+        let index = match &self.keys {
+            Keys::Narrow(keys) => keys.binary_search(&u64::try_from(key.as_u128()).ok()?),
+            Keys::Wide(keys) => keys.binary_search(&key.as_u128()),
+        };
+        index.ok().map(|index| self.scores[index])
+    }
+
+    // keys are symmetric keys, positions already in the book keep their score
+    pub fn extend<B: BoardBits>(&mut self, entries: impl IntoIterator<Item = (B, i8)>) -> BookReport {
+        let entries = entries.into_iter();
+        match &mut self.keys {
+            Keys::Narrow(keys) => merge(keys, &mut self.scores, entries.map(|(key, score)| (key.low_u64(), score))),
+            Keys::Wide(keys) => merge(keys, &mut self.scores, entries.map(|(key, score)| (key.as_u128(), score))),
+        }
+    }
+
+    fn is_sorted(&self) -> bool {
+        match &self.keys {
+            Keys::Narrow(keys) => keys.windows(2).all(|pair| pair[0] < pair[1]),
+            Keys::Wide(keys) => keys.windows(2).all(|pair| pair[0] < pair[1]),
+        }
+    }
+}
+
+// merges entries into the sorted keys and their scores
+fn merge<K: Ord + Copy>(keys: &mut Vec<K>, scores: &mut Vec<i8>, entries: impl Iterator<Item = (K, i8)>) -> BookReport {
+    let mut entries: Vec<(K, i8)> = entries.collect();
+    let inputs = entries.len();
+    // the sort is stable, so the first score of each position is kept
+    entries.sort_by_key(|(key, _)| *key);
+    entries.dedup_by_key(|(key, _)| *key);
+
+    let mut merged_keys = Vec::with_capacity(keys.len() + entries.len());
+    let mut merged_scores = Vec::with_capacity(keys.len() + entries.len());
+    let mut existing = keys.iter().copied().zip(scores.iter().copied()).peekable();
+    let mut stored = 0;
+    for (key, score) in entries {
+        while let Some((existing_key, existing_score)) = existing.next_if(|(existing_key, _)| *existing_key <= key) {
+            merged_keys.push(existing_key);
+            merged_scores.push(existing_score);
+        }
+        if merged_keys.last() != Some(&key) {
+            merged_keys.push(key);
+            merged_scores.push(score);
+            stored += 1;
+        }
+    }
+    for (existing_key, existing_score) in existing {
+        merged_keys.push(existing_key);
+        merged_scores.push(existing_score);
+    }
+    *keys = merged_keys;
+    *scores = merged_scores;
+    BookReport {
+        stored,
+        dropped: inputs - stored,
+    }
+}
+
+impl<B: BoardBits> Default for Explorer<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: BoardBits> Explorer<B> {
     pub fn new() -> Self {
         Self {
             visited: HashSet::new(),
@@ -55,7 +281,11 @@ impl Explorer {
         self.visited.len()
     }
 
-    pub fn explore(&mut self, position: impl Position, mut position_string: String, depth: u8) {
+    pub fn explore(&mut self, mut position: impl Position<Bits = B>, mut position_string: String, depth: u8) {
+        self.explore_from(&mut position, &mut position_string, depth);
+    }
+
+    fn explore_from(&mut self, position: &mut impl Position<Bits = B>, position_string: &mut String, depth: u8) {
         let key = position.symmetric_key();
         if self.visited.contains(&key) || position.get_move_count() > depth {
             return;
@@ -65,16 +295,15 @@ impl Explorer {
             self.output.push(position_string.clone());
         }
 
-        let valid_plays = (0..WIDTH)
-            .filter(|col| position.can_play(*col) && !position.is_winning_move(*col));
-
-        for col in valid_plays {
-            let mut position2 = position.clone();
-            position2.play(col);
-            position_string.push(('0' as u8 + col) as char);
-            self.explore(position2, position_string.clone(), depth);
+        for col in 0..position.dimensions().width {
+            if !position.can_play(col) || position.is_winning_move(col) {
+                continue;
+            }
+            position.play(col);
+            position_string.push((b'0' + col) as char);
+            self.explore_from(position, position_string, depth);
             position_string.pop();
+            position.undo(col);
         }
     }
 }
-
@@ -1,20 +1,210 @@
-// For custom board sizes, change WIDTH and HEIGHT.
-// if WIDTH * (HEIGHT + 1) > 64, then increase BoardType to u128
-// transposition_table might need it's SIZE const changed. Validity is checked during unoptimized build's runtime
-pub type BoardType = u64;
-pub const WIDTH: u8 = 7;
-pub const HEIGHT: u8 = 6;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Not, Shl, Shr, Sub};
 
-pub const BOARD_SIZE: u8 = WIDTH * HEIGHT;
-pub const MIN_SCORE: i8 = -1 * (BOARD_SIZE / 2 + 3) as i8;
+// For custom board sizes, pass a different Dimensions to position_factory and SolverOptions.
+// Boards where width * (height + 1) > 64 need a u128 backed BitBoard (WideBitBoard)
+// transposition_table validity is checked during unoptimized build's runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Dimensions {
+    pub width: u8,
+    pub height: u8,
+}
+
+impl Dimensions {
+    pub const STANDARD: Dimensions = Dimensions { width: 7, height: 6 };
+
+    // columns are played as single digits, so a board can be at most 10 wide
+    // every board that fits in 128 bits can be solved, its scores and table values fit in 8 bits
+    pub fn new(width: u8, height: u8) -> Result<Self, String> {
+        if !(4..=10).contains(&width) {
+            return Err(format!("Width {} must be between 4 and 10", width));
+        }
+        if height < 4 {
+            return Err(format!("Height {} must be at least 4", height));
+        }
+        let dimensions = Self { width, height };
+        if dimensions.bits() > u128::BITS {
+            return Err(format!("A {} board does not fit in 128 bits", dimensions));
+        }
+        Ok(dimensions)
+    }
+
+    pub fn board_size(&self) -> u8 {
+        self.width * self.height
+    }
+
+    // number of bits needed to represent the board, including the extra top row
+    pub fn bits(&self) -> u32 {
+        self.width as u32 * (self.height as u32 + 1)
+    }
+
+    pub fn fits<B: BoardBits>(&self) -> bool {
+        self.bits() <= B::BITS
+    }
+
+    pub fn min_score(&self) -> i8 {
+        -((self.board_size() / 2 + 3) as i8)
+    }
+
+    pub fn max_score(&self) -> i8 {
+        (self.board_size().div_ceil(2) - 3) as i8
+    }
+}
+
+impl Default for Dimensions {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+impl std::fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl std::str::FromStr for Dimensions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('x') {
+            Some((width, height)) => match (width.parse::<u8>(), height.parse::<u8>()) {
+                (Ok(width), Ok(height)) => Self::new(width, height),
+                _ => Err(format!("'{}' is not a valid board size", s)),
+            },
+            None => Err(format!("'{}' is not a valid board size, expected WIDTHxHEIGHT", s)),
+        }
+    }
+}
+
+// Integer types that can back a BitBoard
+pub trait BoardBits:
+    Copy
+    + Send
+    + Eq
+    + Ord
+    + Hash
+    + Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + Shl<u8, Output = Self>
+    + Shr<u8, Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+    const BITS: u32;
+
+    fn count_ones(self) -> u32;
+
+    fn rem_u64(self, n: u64) -> u64;
+
+    fn trailing_zeros(self) -> u32;
 
-pub const MAX_SCORE: i8 = ((BOARD_SIZE + 1) / 2 - 3) as i8;
+    fn low_u32(self) -> u32;
+
+    fn low_u64(self) -> u64;
+
+    fn as_u128(self) -> u128;
+}
+
+macro_rules! impl_board_bits {
+    ($($t:ty),*) => {$(
+        impl BoardBits for $t {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const BITS: u32 = <$t>::BITS;
+
+            fn count_ones(self) -> u32 {
+                <$t>::count_ones(self)
+            }
+
+            fn rem_u64(self, n: u64) -> u64 {
+                (self % n as $t) as u64
+            }
+
+            fn trailing_zeros(self) -> u32 {
+                <$t>::trailing_zeros(self)
+            }
+
+            fn low_u32(self) -> u32 {
+                self as u32
+            }
+
+            fn low_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn as_u128(self) -> u128 {
+                self as u128
+            }
+        }
+    )*};
+}
+
+impl_board_bits!(u64, u128);
+
+// player One always moves first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+impl Player {
+    pub fn to_move(move_count: u8) -> Self {
+        if move_count.is_multiple_of(2) {
+            Player::One
+        }
+        else {
+            Player::Two
+        }
+    }
+
+    pub fn other(&self) -> Self {
+        match self {
+            Player::One => Player::Two,
+            Player::Two => Player::One,
+        }
+    }
+}
+
+impl std::fmt::Display for Player {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Player::One => write!(f, "1"),
+            Player::Two => write!(f, "2"),
+        }
+    }
+}
+
+// cells are (col, row) pairs with row 0 at the bottom of the board
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GameStatus {
+    Win { player: Player, line: [(u8, u8); 4] },
+    Draw,
+    Ongoing,
+}
+
+pub trait Position: Clone + Send {
+    type Bits: BoardBits;
+
+    fn dimensions(&self) -> Dimensions;
 
-pub trait Position: Clone {
     fn can_play(&self, col: u8) -> bool;
 
     fn play(&mut self, col: u8);
 
+    fn can_undo(&self, col: u8) -> bool;
+
+    // removes the top piece of col, which must be the last piece played to restore the previous position
+    fn undo(&mut self, col: u8);
+
     fn is_winning_move(&self, col: u8) -> bool;
 
     fn get_move_count(&self) -> u8;
@@ -23,25 +213,27 @@ pub trait Position: Clone {
 
     fn can_win_next_move(&self) -> bool;
 
-    fn key(&self) -> BoardType;
+    fn key(&self) -> Self::Bits;
 
-    fn symmetric_key(&self) -> BoardType;
+    fn symmetric_key(&self) -> Self::Bits;
 
     fn stringify(&self) -> String;
 
+    fn status(&self) -> GameStatus;
+
     fn min_possible_score(&self) -> i8 {
-        -1 * (BOARD_SIZE - self.get_move_count()) as i8 / 2
+        -((self.dimensions().board_size() - self.get_move_count()) as i8) / 2
     }
 
     fn next_min_possible_score(&self) -> i8 {
-        -1 * (BOARD_SIZE - 2 - self.get_move_count()) as i8 / 2
+        -((self.dimensions().board_size() - 2 - self.get_move_count()) as i8) / 2
     }
 
     fn max_possible_score(&self) -> i8 {
-        ((BOARD_SIZE + 1 - self.get_move_count()) / 2) as i8
+        ((self.dimensions().board_size() + 1 - self.get_move_count()) / 2) as i8
     }
 
     fn next_max_possible_score(&self) -> i8 {
-        ((BOARD_SIZE - 1 - self.get_move_count()) / 2) as i8
+        ((self.dimensions().board_size() - 1 - self.get_move_count()) / 2) as i8
     }
 }
@@ -1,56 +1,119 @@
 use crate::bit_board::BitBoard;
-use crate::position::{Position, HEIGHT, WIDTH, BOARD_SIZE};
+use crate::game::Game;
+use crate::position::{Position, Dimensions, BoardBits, GameStatus};
 
-const BOARD_SIZE_PLUS_ONE: u8 = BOARD_SIZE + 1;
+pub fn create(s: &str) -> Result<BitBoard, String> {
+    create_with_dimensions(s, Dimensions::STANDARD)
+}
+
+// a position to solve, so finished games are rejected, there is nothing left to score
+pub fn create_with_dimensions<B: BoardBits>(s: &str, dimensions: Dimensions) -> Result<BitBoard<B>, String> {
+    let position = parse_with_dimensions(s, dimensions)?;
+    match position.status() {
+        GameStatus::Ongoing => Ok(position),
+        GameStatus::Win { player, .. } => Err(format!("The game is over, player {} won", player)),
+        GameStatus::Draw => Err("The game is over, it is a draw".to_string()),
+    }
+}
+
+// a position stored in a u64 when the board fits in one, and in a u128 otherwise
+// Position has a single type of bits, so match on the storage to use the board
+#[derive(Clone)]
+pub enum AnyBitBoard {
+    Narrow(BitBoard<u64>),
+    Wide(BitBoard<u128>),
+}
+
+impl AnyBitBoard {
+    pub fn dimensions(&self) -> Dimensions {
+        match self {
+            AnyBitBoard::Narrow(position) => position.dimensions(),
+            AnyBitBoard::Wide(position) => position.dimensions(),
+        }
+    }
+}
+
+// like create_with_dimensions, but picks the storage from the dimensions
+pub fn create_auto(s: &str, dimensions: Dimensions) -> Result<AnyBitBoard, String> {
+    if dimensions.fits::<u64>() {
+        create_with_dimensions::<u64>(s, dimensions).map(AnyBitBoard::Narrow)
+    }
+    else {
+        create_with_dimensions::<u128>(s, dimensions).map(AnyBitBoard::Wide)
+    }
+}
 
-pub fn create(s: &str) -> Result<impl Position, String> {
-    match s.len() as u8 {
-        BOARD_SIZE_PLUS_ONE => {
+pub fn parse(s: &str) -> Result<BitBoard, String> {
+    parse_with_dimensions(s, Dimensions::STANDARD)
+}
+
+// any reachable position, finished games included
+pub fn parse_with_dimensions<B: BoardBits>(s: &str, dimensions: Dimensions) -> Result<BitBoard<B>, String> {
+    if !dimensions.fits::<B>() {
+        return Err(format!("A {} board does not fit in {} bits", dimensions, B::BITS));
+    }
+    let Dimensions { width, height } = dimensions;
+    let board_size_plus_one = dimensions.board_size() as usize + 1;
+    match s.len() {
+        len if len == board_size_plus_one => {
             let mut chars = s.chars().fuse();
             let player_up_next = chars.next().unwrap();
             if !matches!(player_up_next, '1' | '2') {
                 return Err(format!("'{}' is not a valid starting player", player_up_next));
             }
-            let mut position: u64 = 0;
-            let mut mask: u64 = 0;
-            let mut move_count: u8 = 0;
+            let mut bit_board = BitBoard::<B>::with_dimensions(dimensions);
             let mut player_moves: u8 = 0;
-            let mut index: u8 = 0;
-            while let Some(c) = chars.next() {
+            for (index, c) in chars.enumerate() {
+                let index = index as u8;
                 if c != '0' {
-                    move_count += 1;
+                    bit_board.move_count += 1;
 
-                    let row = (HEIGHT - 1) - (index / WIDTH);
-                    let col = index % WIDTH;
-                    let value = 1 << (WIDTH * col + row);
+                    let row = (height - 1) - (index / width);
+                    let col = index % width;
+                    let value = B::ONE << ((height + 1) * col + row);
 
-                    mask |= value;
+                    bit_board.mask = bit_board.mask | value;
                     if c == player_up_next {
-                        position |= value;
+                        bit_board.position = bit_board.position | value;
                         player_moves += 1;
                     }
                 }
-                index += 1;
             }
 
-            if move_count / 2  == player_moves {
-                Ok(BitBoard { position, mask, move_count })
-            } else {
-                Err("One player has made at least one extra move".to_string())
-            }
-        },
-        BOARD_SIZE_PLUS_ONE.. => Err("'value' is too long to parse!".to_string()),
-        _ => {
-            let mut bit_board = BitBoard::new();
-            for c in s.chars() {
-                match c.to_digit(10) {
-                    Some(i) if i as u8 >= WIDTH => return Err(format!("{} is too large", i)),
-                    Some(i) if bit_board.can_play(i as u8) => bit_board.play(i as u8),
-                    Some(i) => return Err(format!("Cannot play in column {} on move {}", i, bit_board.move_count + 1)),
-                    None => return Err(format!("{} is not a digit", c)),
-                }
+            if bit_board.move_count / 2 != player_moves {
+                return Err("One player has made at least one extra move".to_string());
             }
+            bit_board.validate()?;
             Ok(bit_board)
         },
+        len if len > board_size_plus_one => Err("'value' is too long to parse!".to_string()),
+        _ => create_game_with_dimensions(s, dimensions).map(Game::into_board),
+    }
+}
+
+pub fn create_game(s: &str) -> Result<Game, String> {
+    create_game_with_dimensions(s, Dimensions::STANDARD)
+}
+
+// only move strings can be turned into a game, board strings have no move history
+pub fn create_game_with_dimensions<B: BoardBits>(s: &str, dimensions: Dimensions) -> Result<Game<B>, String> {
+    if !dimensions.fits::<B>() {
+        return Err(format!("A {} board does not fit in {} bits", dimensions, B::BITS));
+    }
+    if s.len() > dimensions.board_size() as usize {
+        return Err(format!("'{}' has more moves than the board has spaces", s));
+    }
+    let mut game = Game::<B>::with_dimensions(dimensions);
+    for c in s.chars() {
+        if let GameStatus::Win { player, .. } = game.status() {
+            return Err(format!("Player {} already won on move {}", player, game.get_move_count()));
+        }
+        match c.to_digit(10) {
+            Some(i) if i as u8 >= dimensions.width => return Err(format!("{} is too large", i)),
+            Some(i) if game.can_play(i as u8) => game.play(i as u8),
+            Some(i) => return Err(format!("Cannot play in column {} on move {}", i, game.get_move_count() + 1)),
+            None => return Err(format!("{} is not a digit", c)),
+        }
     }
+    Ok(game)
 }
@@ -1,11 +1,17 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Lines};
+use std::io::{self, BufRead, BufReader, Lines, Read, Seek, SeekFrom};
+use zip::ZipArchive;
 
 pub fn read_positions(filepath: &str) -> Lines<BufReader<File>> {
-    let file = File::open(filepath).unwrap();
+    open_positions(filepath).unwrap()
+}
+
+pub fn open_positions(filepath: &str) -> Result<Lines<BufReader<File>>, String> {
+    let file = File::open(filepath).map_err(|error| format!("Could not open {}: {}", filepath, error))?;
     let reader = BufReader::new(file);
 
-    reader.lines()
+    Ok(reader.lines())
 }
 
 pub fn line_to_position_score(line: String) -> (String, i8) {
@@ -14,4 +20,92 @@ pub fn line_to_position_score(line: String) -> (String, i8) {
         (s, Ok(i)) => (s, i),
         (s, ..) => panic!("Failed to parse test case {}", s),
     }
-}
\ No newline at end of file
+}
+
+// a line of a solved file, "position score", tolerating windows line endings
+pub fn parse_position_score(line: &str) -> Result<(&str, i8), String> {
+    match line.trim_end().split_once(' ') {
+        Some((position, score)) => match score.parse::<i8>() {
+            Ok(score) => Ok((position, score)),
+            Err(_) => Err(format!("'{}' is not a score", score)),
+        },
+        None => Err(format!("'{}' is not a position followed by a score", line.trim_end())),
+    }
+}
+
+// counts the leading lines of file that solve positions in order, and cuts off anything after them
+// such as a line left half written by a crash, leaving the file ready to append to
+pub fn resume_solved(file: &mut File, positions: &[String]) -> io::Result<usize> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let mut solved = 0;
+    let mut length = 0;
+    for line in contents.split_inclusive('\n') {
+        let complete = match (line.strip_suffix('\n').and_then(|line| line.split_once(' ')), positions.get(solved)) {
+            (Some((position_string, score)), Some(expected)) => position_string == expected && score.parse::<i8>().is_ok(),
+            _ => false,
+        };
+        if !complete {
+            break;
+        }
+        solved += 1;
+        length += line.len();
+    }
+    file.set_len(length as u64)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(solved)
+}
+
+// the scores of a checkpoint whose lines solve positions in any order
+// a last line without its newline was cut short by a crash, even when it parses, so it is cut off
+// and its position solved again, leaving the file ready to append to
+pub fn resume_checkpoint(file: &mut File) -> io::Result<HashMap<String, i8>> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let mut solved = HashMap::new();
+    let mut length = 0;
+    for line in contents.split_inclusive('\n') {
+        let Some(line) = line.strip_suffix('\n') else {
+            break;
+        };
+        if let Ok((position_string, score)) = parse_position_score(line) {
+            solved.insert(position_string.to_string(), score);
+        }
+        length += line.len() + 1;
+    }
+    file.set_len(length as u64)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(solved)
+}
+
+// calls read_entry with the name and contents of every file in a zip archive, or of the file itself
+pub fn read_solved_files(filepath: &str, mut read_entry: impl FnMut(&str, &mut dyn BufRead)) -> Result<(), String> {
+    let mut file = File::open(filepath).map_err(|error| format!("Could not open {}: {}", filepath, error))?;
+    if !is_zip(&mut file).map_err(|error| format!("Could not read {}: {}", filepath, error))? {
+        read_entry(filepath, &mut BufReader::new(file));
+        return Ok(());
+    }
+
+    let mut archive = ZipArchive::new(file).map_err(|error| format!("Could not read {}: {}", filepath, error))?;
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index).map_err(|error| format!("Could not read {}: {}", filepath, error))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().map_err(|error| format!("Could not read {}: {}", filepath, error))?;
+        let name = format!("{}/{}", filepath, name);
+        read_entry(name.as_str(), &mut BufReader::new(entry));
+    }
+    Ok(())
+}
+
+fn is_zip(file: &mut File) -> std::io::Result<bool> {
+    let mut magic = [0; 4];
+    let is_zip = match file.read_exact(&mut magic) {
+        Ok(()) => &magic == b"PK\x03\x04",
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(error) => return Err(error),
+    };
+    file.seek(SeekFrom::Start(0))?;
+    Ok(is_zip)
+}
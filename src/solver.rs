@@ -1,52 +1,371 @@
 use std::cmp::Ordering;
-use crate::opening_book::{get_opening_book, save_opening_book};
-use crate::transposition_table::TranspositionTable;
-use crate::position::{Position, BOARD_SIZE, WIDTH, MAX_SCORE, MIN_SCORE};
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use crate::opening_book::{self, BookError, OpeningBook, load_opening_book};
+use crate::transposition_table::{PutOutcome, TranspositionTable};
+use crate::position::{Position, Dimensions};
 
+// threads > 1 runs a lazy SMP search: helper threads search the same position with a different
+// move order, sharing the transposition table, and the first one to prove the score wins
+// table_memory is the transposition table budget in bytes
+// opening_books are consulted in order, None uses opening_book::default_filepaths and an empty list runs without a book
+#[derive(Clone)]
 pub struct SolverOptions {
     pub weak: bool,
+    pub dimensions: Dimensions,
+    pub threads: usize,
+    pub table_memory: usize,
+    pub opening_books: Option<Vec<PathBuf>>,
 }
 
-impl SolverOptions {
-    fn new() -> Self {
+impl Default for SolverOptions {
+    fn default() -> Self {
         Self {
             weak: false,
+            dimensions: Dimensions::STANDARD,
+            threads: 1,
+            table_memory: TranspositionTable::DEFAULT_MEMORY,
+            opening_books: None,
+        }
+    }
+}
+
+// a budget for a single search, None means unlimited
+#[derive(Clone, Copy, Default)]
+pub struct SearchLimits {
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+// min and max are proven bounds on the score, they are equal once the search completed
+// best_move is the move to play, None only when no column is playable
+// best_move_proven tells whether it is proven to score at least min, otherwise it is the best guess of the move order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    pub min: i8,
+    pub max: i8,
+    pub best_move: Option<u8>,
+    pub best_move_proven: bool,
+}
+
+impl SearchResult {
+    pub fn score(&self) -> Option<i8> {
+        if self.min == self.max {
+            Some(self.min)
+        }
+        else {
+            None
         }
     }
 }
 
+// returned by a search that was stopped through a CancelHandle, holding the bounds proven so far
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled(pub SearchResult);
+
+// stops the search that was running on a Solver when the handle was made, or the next one if none was
+// searches are numbered and a handle only cancels its own, so a cancel arriving late does not stop a later search
+// get a new handle for every search
+#[derive(Clone)]
+pub struct CancelHandle {
+    state: Arc<CancelState>,
+    generation: u64,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.state.cancelled.fetch_max(self.generation, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(AtomicOrdering::Relaxed) == self.generation
+    }
+}
+
+// generation is the number of the search running or about to run, it moves on when a search finishes
+// cancelled is the latest search a handle cancelled
+struct CancelState {
+    generation: AtomicU64,
+    cancelled: AtomicU64,
+}
+
+impl CancelState {
+    fn new() -> Self {
+        Self {
+            // searches are numbered from 1, so that 0 cancels none of them
+            generation: AtomicU64::new(1),
+            cancelled: AtomicU64::new(0),
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(AtomicOrdering::Relaxed) == self.generation.load(AtomicOrdering::Relaxed)
+    }
+}
+
+// counters for a single search, summed over all threads in a parallel search
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub table_hits: u64,
+    pub table_misses: u64,
+    pub table_collisions: u64,
+    pub table_replacements: u64,
+    pub book_hits: u64,
+    pub iterations: u32,
+    pub elapsed: Duration,
+}
+
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes += other.nodes;
+        self.table_hits += other.table_hits;
+        self.table_misses += other.table_misses;
+        self.table_collisions += other.table_collisions;
+        self.table_replacements += other.table_replacements;
+        self.book_hits += other.book_hits;
+        self.iterations += other.iterations;
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl SearchStats {
+    pub fn nodes_per_second(&self) -> f64 {
+        self.nodes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl std::fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "nodes: {}, table hits: {}, table misses: {}, table collisions: {}, table replacements: {}, book hits: {}, iterations: {}, elapsed: {:.3}s, nodes/s: {:.0}",
+            self.nodes, self.table_hits, self.table_misses, self.table_collisions, self.table_replacements, self.book_hits,
+            self.iterations, self.elapsed.as_secs_f64(), self.nodes_per_second())
+    }
+}
+
+// reported after every null window iteration of a search
+pub struct Progress {
+    pub min: i8,
+    pub max: i8,
+    pub stats: SearchStats,
+}
+
+pub type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
 pub struct Solver {
     options: SolverOptions,
-    opening_book: TranspositionTable,
-    transposition_table: TranspositionTable,
-    node_count: u64,
+    opening_books: Vec<Arc<OpeningBook>>,
+    opening_book_errors: Vec<BookError>,
+    transposition_table: Arc<TranspositionTable>,
+    stats: SearchStats,
+    progress_callback: Option<ProgressCallback>,
+    limits: SearchLimits,
+    search_start: Instant,
+    stopped: bool,
+    cancellable: bool,
+    cancel_state: Arc<CancelState>,
+    abort_flag: Arc<AtomicBool>,
+    helper_id: usize,
+    root_move_count: u8,
+    root_best_move: Option<u8>,
 }
 
 impl Solver {
+    // how many nodes are searched between checks of the search limits
+    const LIMIT_CHECK_INTERVAL: u64 = 1 << 12;
+
     pub fn new(options: Option<SolverOptions>) -> Self {
+        let options = options.unwrap_or_default();
+        let dimensions = options.dimensions;
+        let filepaths = options.opening_books.clone()
+            .unwrap_or_else(|| opening_book::default_filepaths(dimensions));
+        let mut opening_books = Vec::new();
+        let mut opening_book_errors = Vec::new();
+        for filepath in filepaths {
+            match load_opening_book(&filepath, dimensions) {
+                Ok(opening_book) => opening_books.push(Arc::new(opening_book)),
+                Err(error) => opening_book_errors.push(error),
+            }
+        }
+        let transposition_table = Arc::new(TranspositionTable::with_memory(dimensions, options.table_memory));
         Self {
-            options: options.unwrap_or(SolverOptions::new()),
-            opening_book: get_opening_book(),
-            transposition_table: TranspositionTable::new(),
-            node_count: 0,
+            options,
+            opening_books,
+            opening_book_errors,
+            transposition_table,
+            stats: SearchStats::default(),
+            progress_callback: None,
+            limits: SearchLimits::default(),
+            search_start: Instant::now(),
+            stopped: false,
+            cancellable: false,
+            cancel_state: Arc::new(CancelState::new()),
+            abort_flag: Arc::new(AtomicBool::new(false)),
+            helper_id: 0,
+            root_move_count: 0,
+            root_best_move: None,
         }
     }
 
-    pub fn save(self) -> std::io::Result<()> {
-        save_opening_book(self.opening_book)
+    // the books that could not be loaded, the solver runs without them
+    pub fn opening_book_errors(&self) -> &[BookError] {
+        &self.opening_book_errors
+    }
+
+    // consulted after the books that were already loaded
+    pub fn add_opening_book(&mut self, opening_book: OpeningBook) {
+        self.opening_books.push(Arc::new(opening_book));
+    }
+
+    // replaces the transposition table with one saved by save_table for the same options
+    pub fn load_table(&mut self, filepath: &str) -> Result<(), String> {
+        let table = TranspositionTable::load(filepath, self.options.dimensions, self.options.table_memory)?;
+        self.transposition_table = Arc::new(table);
+        Ok(())
     }
 
+    pub fn save_table(&self, filepath: &str) -> std::io::Result<()> {
+        self.transposition_table.save(filepath, self.options.dimensions)
+    }
+
+    // counters of the last search
+    pub fn stats(&self) -> SearchStats {
+        self.stats
+    }
+
+    pub fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.progress_callback = callback;
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            state: self.cancel_state.clone(),
+            generation: self.cancel_state.generation.load(AtomicOrdering::Relaxed),
+        }
+    }
+
+    // ignores cancellation, use try_solve to solve from a cancellable worker
     pub fn solve(&mut self, position: impl Position) -> i8 {
-        if position.can_win_next_move() {
-            return position.max_possible_score();
+        match self.search(position, SearchLimits::default(), false) {
+            Ok(result) => result.min,
+            Err(_) => unreachable!("uncancellable search was cancelled"),
+        }
+    }
+
+    pub fn try_solve(&mut self, position: impl Position) -> Result<i8, Cancelled> {
+        self.search(position, SearchLimits::default(), true).map(|result| result.min)
+    }
+
+    // searches until the score is proven, a limit runs out or the search is cancelled
+    pub fn solve_limited(&mut self, position: impl Position, limits: SearchLimits) -> Result<SearchResult, Cancelled> {
+        self.search(position, limits, true)
+    }
+
+    fn search(&mut self, position: impl Position, limits: SearchLimits, cancellable: bool) -> Result<SearchResult, Cancelled> {
+        debug_assert!(position.dimensions() == self.options.dimensions);
+
+        self.stats = SearchStats::default();
+        let result = if self.options.threads > 1 {
+            self.parallel_search(position, limits, cancellable)
+        }
+        else {
+            self.serial_search(position, limits, cancellable)
+        };
+        // handles made from here on are for the next search
+        self.cancel_state.generation.fetch_add(1, AtomicOrdering::Relaxed);
+        result
+    }
+
+    fn parallel_search(&mut self, position: impl Position, limits: SearchLimits, cancellable: bool) -> Result<SearchResult, Cancelled> {
+        let helpers = (1..self.options.threads)
+            .map(|helper_id| self.helper(helper_id))
+            .collect::<Vec<_>>();
+        let helper_result: Mutex<Option<SearchResult>> = Mutex::new(None);
+
+        let result = thread::scope(|scope| {
+            let handles = helpers.into_iter()
+                .map(|mut helper| {
+                    let position = position.clone();
+                    let helper_result = &helper_result;
+                    scope.spawn(move || {
+                        if let Ok(result) = helper.serial_search(position, SearchLimits::default(), false) {
+                            if result.score().is_some() {
+                                helper_result.lock().unwrap().get_or_insert(result);
+                                helper.abort_flag.store(true, AtomicOrdering::Relaxed);
+                            }
+                        }
+                        helper.stats
+                    })
+                })
+                .collect::<Vec<_>>();
+            let result = self.serial_search(position, limits, cancellable);
+            self.abort_flag.store(true, AtomicOrdering::Relaxed);
+            for handle in handles {
+                let helper_stats = handle.join().unwrap();
+                // elapsed time and iterations are those of the main search
+                self.stats += SearchStats { elapsed: Duration::ZERO, iterations: 0, ..helper_stats };
+            }
+            result
+        });
+        self.abort_flag.store(false, AtomicOrdering::Relaxed);
+
+        match (result, helper_result.into_inner().unwrap()) {
+            (Ok(result), _) if result.score().is_some() => Ok(result),
+            (_, Some(helper_result)) => Ok(helper_result),
+            (result, None) => result,
+        }
+    }
+
+    // shares the tables and the abort flag, but searches in a different order
+    fn helper(&self, helper_id: usize) -> Solver {
+        Solver {
+            options: SolverOptions { threads: 1, ..self.options.clone() },
+            opening_books: self.opening_books.clone(),
+            opening_book_errors: Vec::new(),
+            transposition_table: self.transposition_table.clone(),
+            stats: SearchStats::default(),
+            progress_callback: None,
+            limits: SearchLimits::default(),
+            search_start: Instant::now(),
+            stopped: false,
+            cancellable: false,
+            cancel_state: Arc::new(CancelState::new()),
+            abort_flag: self.abort_flag.clone(),
+            helper_id,
+            root_move_count: 0,
+            root_best_move: None,
+        }
+    }
+
+    fn serial_search(&mut self, mut position: impl Position, limits: SearchLimits, cancellable: bool) -> Result<SearchResult, Cancelled> {
+        if let Some(col) = (0..position.dimensions().width).find(|col| position.can_play(*col) && position.is_winning_move(*col)) {
+            let score = position.max_possible_score();
+            return Ok(SearchResult { min: score, max: score, best_move: Some(col), best_move_proven: true });
         }
 
+        self.limits = limits;
+        self.search_start = Instant::now();
+        self.stopped = false;
+        self.cancellable = cancellable;
+        self.root_move_count = position.get_move_count();
+        self.root_best_move = None;
+
         let mut min = position.min_possible_score();
         let mut max = position.max_possible_score();
         if self.options.weak {
             min = -1;
             max = 1;
         }
+        // the book has no moves, a single null window search at its score finds the move proving it
+        if let Some(score) = self.book_score(&position).filter(|score| min < *score && *score <= max) {
+            self.stats.book_hits += 1;
+            min = score - 1;
+            max = score;
+        }
 
         loop {
             let mut median = min + (max - min) / 2;
@@ -58,7 +377,21 @@ impl Solver {
             else if median >= 0 && half_max > median {
                 median = half_max
             }
-            let score = self.negamax(position.clone(), median, median + 1);
+            let score = self.negamax(&mut position, median, median + 1);
+            self.stats.elapsed = self.search_start.elapsed();
+
+            if self.stopped {
+                let result = SearchResult {
+                    min,
+                    max,
+                    best_move: self.root_best_move.or_else(|| self.guessed_move(&position)),
+                    best_move_proven: self.root_best_move.is_some(),
+                };
+                if self.is_cancelled() {
+                    return Err(Cancelled(result));
+                }
+                return Ok(result);
+            }
 
             if score <= median {
                 max = score;
@@ -67,14 +400,114 @@ impl Solver {
                 min = score;
             }
 
+            self.stats.iterations += 1;
+            if let Some(callback) = self.progress_callback.as_mut() {
+                callback(&Progress { min, max, stats: self.stats });
+            }
+
             if min >= max {
-                return score;
+                let best_move = self.root_best_move.or_else(|| self.guessed_move(&position));
+                return Ok(SearchResult { min: score, max: score, best_move, best_move_proven: true });
             }
         }
     }
 
-    pub fn analyze(&mut self, position: impl Position) -> Vec<Option<i8>> {
-        (0..WIDTH)
+    fn book_score(&self, position: &impl Position) -> Option<i8> {
+        let key = position.symmetric_key();
+        self.opening_books.iter().find_map(|opening_book| opening_book.get(key))
+    }
+
+    // the table move if it does not lose at once, otherwise the first such move in search order
+    // once a search completed without a move beating a null window at the root, the score is the lowest
+    // the window allowed, which every move that does not lose at once reaches, so the guess is proven
+    fn guessed_move(&self, position: &impl Position) -> Option<u8> {
+        let mut moves = position.get_moves_with_weight();
+        moves.sort_by(|a, b| self.order(position.dimensions().width, a, b));
+        let table_move = self.table_move(position);
+        moves.iter()
+            .find(|(col, _)| Some(*col) == table_move)
+            .or(moves.first())
+            .map(|(col, _)| *col)
+            .or_else(|| (0..position.dimensions().width).find(|col| position.can_play(*col)))
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellable && self.cancel_state.is_cancelled()
+    }
+
+    fn out_of_budget(&self) -> bool {
+        self.limits.nodes.is_some_and(|nodes| self.stats.nodes >= nodes)
+            || self.limits.time.is_some_and(|time| self.search_start.elapsed() >= time)
+            || self.is_cancelled()
+            || self.abort_flag.load(AtomicOrdering::Relaxed)
+    }
+
+    // the score together with the moves both players make to reach it
+    pub fn solve_with_pv(&mut self, mut position: impl Position) -> (i8, Vec<u8>) {
+        let score = self.solve(position.clone());
+        let mut principal_variation = Vec::new();
+        let mut expected = score;
+        let width = position.dimensions().width;
+        let mid = width / 2;
+        let mut cols = (0..width).collect::<Vec<_>>();
+        cols.sort_by_key(|col| mid.abs_diff(*col));
+
+        while position.get_move_count() < position.dimensions().board_size() {
+            if let Some(col) = cols.iter().copied().find(|col| position.can_play(*col) && position.is_winning_move(*col)) {
+                principal_variation.push(col);
+                break;
+            }
+            let mut next = None;
+            let table_move = self.table_move(&position);
+            for col in table_move.into_iter().chain(cols.iter().copied().filter(|col| Some(*col) != table_move)) {
+                if !position.can_play(col) {
+                    continue;
+                }
+                position.play(col);
+                let child_score = self.solve(position.clone());
+                if self.same_result(-child_score, expected) {
+                    next = Some((col, child_score));
+                    break;
+                }
+                position.undo(col);
+            }
+            match next {
+                Some((col, child_score)) => {
+                    principal_variation.push(col);
+                    expected = child_score;
+                },
+                None => break,
+            }
+        }
+        (score, principal_variation)
+    }
+
+    // follows the cutoff moves stored in the table, without searching
+    // the line is only as good as the table, a move stored from a lower bound need not be optimal
+    pub fn table_line(&self, mut position: impl Position) -> Vec<u8> {
+        let mut line = Vec::new();
+        while let Some(col) = self.table_move(&position) {
+            if !position.can_play(col) {
+                break;
+            }
+            line.push(col);
+            if position.is_winning_move(col) {
+                break;
+            }
+            position.play(col);
+        }
+        line
+    }
+
+    fn table_move(&self, position: &impl Position) -> Option<u8> {
+        let key = position.symmetric_key();
+        self.transposition_table.probe(key)
+            .and_then(|entry| entry.best_move)
+            .map(|col| Self::mirror(position.dimensions().width, col, position.key() != key))
+    }
+
+    pub fn analyze(&mut self, mut position: impl Position) -> Vec<Option<i8>> {
+        (0..position.dimensions().width)
             .map(|col| {
                 if !position.can_play(col) {
                     None
@@ -83,9 +516,10 @@ impl Solver {
                     Some(position.max_possible_score())
                 }
                 else {
-                    let mut position2 = position.clone();
-                    position2.play(col);
-                    Some(-self.solve(position2))
+                    position.play(col);
+                    let score = -self.solve(position.clone());
+                    position.undo(col);
+                    Some(score)
                 }
             })
             .collect()
@@ -100,37 +534,85 @@ impl Solver {
             .map(|(index, _)| index)
     }
 
-    fn negamax(&mut self, position: impl Position, mut alpha: i8, mut beta: i8) -> i8 {
+    // the best move found within limits, even when the search is cancelled, use solve_limited to know whether it is proven
+    pub fn best_move_limited(&mut self, position: impl Position, limits: SearchLimits) -> Option<usize> {
+        match self.solve_limited(position, limits) {
+            Ok(result) | Err(Cancelled(result)) => result.best_move.map(usize::from),
+        }
+    }
+
+    // weak scores only tell win, draw or loss apart
+    fn same_result(&self, a: i8, b: i8) -> bool {
+        if self.options.weak {
+            a.signum() == b.signum()
+        }
+        else {
+            a == b
+        }
+    }
+
+    fn negamax(&mut self, position: &mut impl Position, mut alpha: i8, mut beta: i8) -> i8 {
         debug_assert!(alpha < beta);
 
-        self.node_count += 1;
+        self.stats.nodes += 1;
+        if self.stats.nodes.is_multiple_of(Self::LIMIT_CHECK_INTERVAL) && self.out_of_budget() {
+            self.stopped = true;
+        }
+        if self.stopped {
+            return alpha;
+        }
+
+        let dimensions = self.options.dimensions;
+        let min_score = dimensions.min_score();
 
         let mut next_moves_with_weight = position.get_moves_with_weight();
-        if next_moves_with_weight.len() == 0 {
+        if next_moves_with_weight.is_empty() {
             return position.min_possible_score();
         }
 
-        if position.get_move_count() >= (BOARD_SIZE - 2) {
+        if position.get_move_count() >= (dimensions.board_size() - 2) {
             return 0;
         }
 
+        // the book is consulted for the root before the search and table values are not used at the root,
+        // a score returned from here would come without a move
+        let is_root = position.get_move_count() == self.root_move_count;
         let key = position.symmetric_key();
-        if let Some(val) = self.opening_book.get(key) {
-            return val;
+        if !is_root {
+            if let Some(val) = self.book_score(position) {
+                self.stats.book_hits += 1;
+                return val;
+            }
         }
 
         let mut min: i8 = position.next_min_possible_score();
         let mut max: i8 = position.next_max_possible_score();
-        match self.transposition_table.get(key) {
-            Some(val) => {
-                if val > MAX_SCORE - MIN_SCORE + 1 {
-                    min = val + 2 * MIN_SCORE - MAX_SCORE - 2;
-                }
-                else {
-                    max = val + MIN_SCORE - 1;
-                }
-            },
-            None => (),
+        let entry = self.transposition_table.probe(key);
+        // entries are shared with the mirrored position, so their moves are stored for the symmetric key
+        let mirrored = position.key() != key;
+        let hash_move = entry
+            .and_then(|entry| entry.best_move)
+            .map(|col| Self::mirror(dimensions.width, col, mirrored));
+        let value = if is_root {
+            None
+        }
+        else {
+            if entry.is_some() {
+                self.stats.table_hits += 1;
+            }
+            else {
+                self.stats.table_misses += 1;
+            }
+            entry.map(|entry| entry.value)
+        };
+        if let Some(val) = value {
+            let board_size = dimensions.board_size();
+            if val > board_size + 1 {
+                min = (val - board_size - 2) as i8 + min_score;
+            }
+            else {
+                max = (val - 1) as i8 + min_score;
+            }
         }
 
         if alpha < min {
@@ -147,25 +629,25 @@ impl Solver {
             }
         }
 
-        fn order(a: &(u8, u8), b: &(u8, u8)) -> Ordering {
-            let mid = WIDTH / 2;
-
-            let (a_col, a_weight) = a;
-            let (b_col, b_weight) = b;
-            if a_weight == b_weight {
-                mid.abs_diff(*a_col).cmp(&mid.abs_diff(*b_col))
-            } else {
-                b_weight.cmp(&a_weight)
-            }
+        next_moves_with_weight.sort_by(|a, b| self.order(dimensions.width, a, b));
+        // the move that produced a cutoff last time is likely to do so again
+        if let Some(index) = next_moves_with_weight.iter().position(|(col, _)| Some(*col) == hash_move) {
+            next_moves_with_weight[..=index].rotate_right(1);
         }
-        next_moves_with_weight.sort_by(order);
 
         for (col, _) in next_moves_with_weight {
-            let mut position2 = position.clone();
-            position2.play(col);
-            let score = -self.negamax(position2, -beta, -alpha);
+            position.play(col);
+            let score = -self.negamax(position, -beta, -alpha);
+            position.undo(col);
+            if self.stopped {
+                return alpha;
+            }
             if score >= beta {
-                self.transposition_table.put(key, score + MAX_SCORE - 2 * MIN_SCORE + 2);
+                if is_root {
+                    self.root_best_move = Some(col);
+                }
+                let best_move = Self::mirror(dimensions.width, col, mirrored);
+                self.put(position, key, Self::lower_bound_value(dimensions, score), Some(best_move));
                 return score;
             }
             if score > alpha {
@@ -173,9 +655,52 @@ impl Solver {
             }
         }
 
-        self.transposition_table.put(key, alpha - MIN_SCORE + 1);
-        return alpha;
+        self.put(position, key, Self::upper_bound_value(dimensions, alpha), None);
+        alpha
     }
-}
 
+    // table values are scores offset from min_score, upper bounds in 1..=board_size + 1 and lower bounds above that
+    // max_score - min_score is board_size, so both fit in a u8 for any board that fits in 128 bits
+    fn upper_bound_value(dimensions: Dimensions, score: i8) -> u8 {
+        (score - dimensions.min_score()) as u8 + 1
+    }
+
+    fn lower_bound_value(dimensions: Dimensions, score: i8) -> u8 {
+        (score - dimensions.min_score()) as u8 + dimensions.board_size() + 2
+    }
 
+    // positions with more empty cells saved bigger searches, so they are kept over shallower ones
+    fn put<P: Position>(&mut self, position: &P, key: P::Bits, value: u8, best_move: Option<u8>) {
+        let weight = position.dimensions().board_size() - position.get_move_count();
+        match self.transposition_table.put(key, value, weight, best_move) {
+            PutOutcome::Inserted | PutOutcome::Updated => {},
+            PutOutcome::Collided => self.stats.table_collisions += 1,
+            PutOutcome::Replaced => {
+                self.stats.table_collisions += 1;
+                self.stats.table_replacements += 1;
+            },
+        }
+    }
+
+    fn mirror(width: u8, col: u8, mirrored: bool) -> u8 {
+        if mirrored {
+            width - 1 - col
+        }
+        else {
+            col
+        }
+    }
+
+    // moves creating the most winning positions first, then the most central
+    // helpers shift the centre so that each thread explores a different part of the tree first
+    fn order(&self, width: u8, a: &(u8, u8), b: &(u8, u8)) -> Ordering {
+        let mid = ((width as usize / 2 + self.helper_id) % width as usize) as u8;
+        let (a_col, a_weight) = a;
+        let (b_col, b_weight) = b;
+        if a_weight == b_weight {
+            mid.abs_diff(*a_col).cmp(&mid.abs_diff(*b_col))
+        } else {
+            b_weight.cmp(a_weight)
+        }
+    }
+}
@@ -1,65 +1,432 @@
 use std::collections::HashMap;
-use std::mem::size_of;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::atomic::{self, AtomicU64, Ordering};
 use bincode::{Decode, Encode};
-use crate::position::{BoardType, WIDTH, HEIGHT};
+use bincode::config::{self, Configuration};
+use crate::position::{BoardBits, Dimensions};
 
-type KeyType = u32;
+// Entries are grouped into buckets of BUCKET_SIZE, a key can be stored in any entry of bucket key % buckets.
+// Only the low bits of each key are stored, the rest are implied by the bucket.
+// This is exact as long as buckets * 2^(stored key bits) covers every possible position key.
+// Each entry also keeps the column that produced a cutoff, if any, and a weight, the number of empty cells left in its position, which approximates
+// the size of the subtree it saves. A full bucket evicts its lightest entry.
+// Entries are shared between search threads, so a key, move, weight and value are packed into a single
+// atomic word. Boards too wide for that fall back to storing full keys in a WideEntry, which is lock free too
+#[derive(Encode, Decode)]
+enum Entries {
+    Packed(Vec<AtomicU64>),
+    Wide(Vec<WideEntry>),
+}
+
+// A seqlock: state packs a sequence number where a packed entry keeps its key, above the move, weight and value.
+// Writers make the sequence odd while they change the key, and readers give up on an entry that is odd
+// or changed while they read it. Nobody waits: a writer that finds the entry being written drops its store,
+// which the table allows for any store
+#[derive(Encode, Decode)]
+struct WideEntry {
+    state: AtomicU64,
+    key: [AtomicU64; 2],
+}
+
+impl WideEntry {
+    // like the packed EMPTY, a key no position has
+    const EMPTY_KEY: u128 = u128::MAX;
+
+    const SEQUENCE_MASK: u64 = (1 << TranspositionTable::PACKED_KEY_BITS) - 1;
+
+    fn empty() -> Self {
+        Self {
+            state: AtomicU64::new(TranspositionTable::pack(0, TranspositionTable::NO_MOVE, 0, 0)),
+            key: [AtomicU64::new(Self::EMPTY_KEY as u64), AtomicU64::new((Self::EMPTY_KEY >> 64) as u64)],
+        }
+    }
+
+    // the key and state, unless a writer got in the way
+    fn read(&self) -> Option<(u128, u64)> {
+        let state = self.state.load(Ordering::Acquire);
+        if Self::sequence(state) % 2 == 1 {
+            return None;
+        }
+        let key = self.key();
+        atomic::fence(Ordering::Acquire);
+        if self.state.load(Ordering::Relaxed) != state {
+            return None;
+        }
+        Some((key, state))
+    }
+
+    fn write(&self, key: u128, best_move: u64, weight: u8, value: u8) {
+        let state = self.state.load(Ordering::Relaxed);
+        let sequence = Self::sequence(state);
+        if sequence % 2 == 1 {
+            return;
+        }
+        let writing = (state & !(Self::SEQUENCE_MASK << TranspositionTable::PACKED_KEY_SHIFT))
+            | (sequence + 1) << TranspositionTable::PACKED_KEY_SHIFT;
+        if self.state.compare_exchange(state, writing, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return;
+        }
+        atomic::fence(Ordering::Release);
+        self.key[0].store(key as u64, Ordering::Relaxed);
+        self.key[1].store((key >> 64) as u64, Ordering::Relaxed);
+        let sequence = (sequence + 2) & Self::SEQUENCE_MASK;
+        self.state.store(TranspositionTable::pack(sequence, best_move, weight, value), Ordering::Release);
+    }
+
+    fn sequence(state: u64) -> u64 {
+        state >> TranspositionTable::PACKED_KEY_SHIFT
+    }
+
+    fn key(&self) -> u128 {
+        self.key[0].load(Ordering::Relaxed) as u128 | (self.key[1].load(Ordering::Relaxed) as u128) << 64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub value: u8,
+    pub best_move: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PutOutcome {
+    // stored in an empty bucket
+    Inserted,
+    // stored in an empty entry of a bucket already holding other positions
+    Collided,
+    // replaced an older value for the same position
+    Updated,
+    // the bucket was full, so the lightest entry for another position was evicted
+    Replaced,
+}
+
+#[derive(Encode, Decode)]
+struct TableHeader {
+    magic: [u8; 4],
+    version: u16,
+    width: u8,
+    height: u8,
+    buckets: u64,
+    packed: bool,
+}
+
+impl TableHeader {
+    const MAGIC: [u8; 4] = *b"C4TT";
+
+    // 2 stores wide entries as seqlocks
+    const VERSION: u16 = 2;
+}
 
 #[derive(Encode, Decode)]
 pub struct TranspositionTable
 {
-    keys: Vec<KeyType>,
-    values: Vec<i8>
+    buckets: usize,
+    entries: Entries,
 }
 
 impl TranspositionTable {
 
-    const SIZE: usize = 3_000_017;
+    const BUCKET_SIZE: usize = 4;
+
+    // about the 15MB of the 3_000_017 single entry table this replaced
+    const DEFAULT_BUCKETS: usize = 468_739;
+
+    pub const DEFAULT_MEMORY: usize = Self::DEFAULT_BUCKETS * Self::BUCKET_SIZE * size_of::<AtomicU64>();
+
+    const VALUE_BITS: u32 = u8::BITS;
+
+    const WEIGHT_BITS: u32 = u8::BITS;
+
+    // columns are single digits, so they fit in 4 bits with one value left over for no move
+    const MOVE_BITS: u32 = 4;
 
-    pub fn new() -> Self {
+    const NO_MOVE: u64 = (1 << Self::MOVE_BITS) - 1;
+
+    const PACKED_KEY_BITS: u32 = u64::BITS - Self::MOVE_BITS - Self::WEIGHT_BITS - Self::VALUE_BITS;
+
+    const PACKED_KEY_SHIFT: u32 = u64::BITS - Self::PACKED_KEY_BITS;
+
+    // no position has a key with all of its low bits set
+    const EMPTY: u64 = u64::MAX;
+
+    pub fn new(dimensions: Dimensions) -> Self {
+        Self::with_memory(dimensions, Self::DEFAULT_MEMORY)
+    }
+
+    // the number of buckets is the largest prime that fits in memory_bytes
+    pub fn with_memory(dimensions: Dimensions, memory_bytes: usize) -> Self {
+        let position_key_bits = dimensions.bits();
+        let (buckets, packed) = Self::layout(dimensions, memory_bytes);
+        let entries = if packed {
+            Entries::Packed((0..buckets * Self::BUCKET_SIZE).map(|_| AtomicU64::new(Self::EMPTY)).collect())
+        }
+        else {
+            Entries::Wide((0..buckets * Self::BUCKET_SIZE).map(|_| WideEntry::empty()).collect())
+        };
         debug_assert!({
-            let table_key_bits = size_of::<KeyType>() * 8;
-            let position_key_bits = WIDTH * (HEIGHT + 1);
-            let min_size: BoardType = (1 << position_key_bits) / (1 << table_key_bits);
-            get_prime_greater_than_or_equal(min_size) <= Self::SIZE as BoardType
-                && is_prime(Self::SIZE as BoardType)
+            let table_key_bits = entries.key_bits();
+            let min_size: u128 = 1 << position_key_bits.saturating_sub(table_key_bits);
+            min_size <= buckets as u128
+                && get_prime_greater_than_or_equal(min_size as u64) <= buckets as u64
+                && is_prime(buckets as u64)
         });
         Self {
-            keys: vec![KeyType::MAX; Self::SIZE],
-            values: vec![0; Self::SIZE],
+            buckets,
+            entries,
         }
     }
 
-    pub fn put(&mut self, key: BoardType, value: i8) {
-        let index = Self::index(key);
-        self.keys[index] = key as KeyType;
-        self.values[index] = value;
+    // the table is written after a header, so that it is only loaded back for the same board and size
+    pub fn save(&self, filepath: &str, dimensions: Dimensions) -> std::io::Result<()> {
+        let header = TableHeader {
+            magic: TableHeader::MAGIC,
+            version: TableHeader::VERSION,
+            width: dimensions.width,
+            height: dimensions.height,
+            buckets: self.buckets as u64,
+            packed: matches!(self.entries, Entries::Packed(_)),
+        };
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        bincode::encode_into_std_write(&header, &mut writer, bincode_config()).map_err(std::io::Error::other)?;
+        bincode::encode_into_std_write(self, &mut writer, bincode_config()).map_err(std::io::Error::other)?;
+        writer.flush()
     }
 
-    pub fn get(&self, key: BoardType) -> Option<i8> {
-        let index = Self::index(key);
-        if self.keys[index] != key as KeyType
-        {
-            return None;
+    // fails unless the file holds a table saved for dimensions with the size memory_bytes would give
+    pub fn load(filepath: &str, dimensions: Dimensions, memory_bytes: usize) -> Result<Self, String> {
+        let file = File::open(filepath).map_err(|error| format!("Could not open {}: {}", filepath, error))?;
+        let mut reader = BufReader::new(file);
+        let header: TableHeader = bincode::decode_from_std_read(&mut reader, bincode_config())
+            .map_err(|error| format!("Could not read the header of {}: {}", filepath, error))?;
+        if header.magic != TableHeader::MAGIC {
+            return Err(format!("{} is not a saved transposition table", filepath));
+        }
+        if header.version != TableHeader::VERSION {
+            return Err(format!("{} has format version {}, only version {} is supported", filepath, header.version, TableHeader::VERSION));
+        }
+        if (header.width, header.height) != (dimensions.width, dimensions.height) {
+            return Err(format!("{} was saved for a {}x{} board, not {}", filepath, header.width, header.height, dimensions));
+        }
+        let (buckets, packed) = Self::layout(dimensions, memory_bytes);
+        if (header.buckets, header.packed) != (buckets as u64, packed) {
+            return Err(format!("{} was saved with {} buckets, not the {} that fit in {} bytes",
+                filepath, header.buckets, buckets, memory_bytes));
+        }
+        let table: Self = bincode::decode_from_std_read(&mut reader, bincode_config())
+            .map_err(|error| format!("Could not read {}: {}", filepath, error))?;
+        let entries = match &table.entries {
+            Entries::Packed(entries) => entries.len(),
+            Entries::Wide(entries) => entries.len(),
+        };
+        if table.buckets != buckets || entries != buckets * Self::BUCKET_SIZE {
+            return Err(format!("{} does not match its header", filepath));
+        }
+        Ok(table)
+    }
+
+    // bytes used by the entries
+    pub fn memory(&self) -> usize {
+        let entry_size = match &self.entries {
+            Entries::Packed(_) => size_of::<AtomicU64>(),
+            Entries::Wide(_) => size_of::<WideEntry>(),
+        };
+        self.buckets * Self::BUCKET_SIZE * entry_size
+    }
+
+    // weight is the number of empty cells left in the position
+    // updating a position without a best move keeps the one already stored
+    pub fn put<B: BoardBits>(&self, key: B, value: u8, weight: u8, best_move: Option<u8>) -> PutOutcome {
+        let bucket = self.bucket(key);
+        match &self.entries {
+            Entries::Packed(entries) => {
+                let packed_key = Self::packed_key(key);
+                let mut target = 0;
+                let mut target_weight = u8::MAX;
+                let mut outcome = PutOutcome::Replaced;
+                let mut shared = false;
+                let mut best_move = best_move.map_or(Self::NO_MOVE, u64::from);
+                for (i, entry) in entries[bucket..bucket + Self::BUCKET_SIZE].iter().enumerate() {
+                    let entry = entry.load(Ordering::Relaxed);
+                    if entry >> Self::PACKED_KEY_SHIFT == packed_key {
+                        target = i;
+                        outcome = PutOutcome::Updated;
+                        if best_move == Self::NO_MOVE {
+                            best_move = Self::unpack_move(entry);
+                        }
+                        break;
+                    }
+                    if entry == Self::EMPTY {
+                        if outcome == PutOutcome::Replaced {
+                            target = i;
+                            outcome = PutOutcome::Inserted;
+                        }
+                        continue;
+                    }
+                    shared = true;
+                    if outcome == PutOutcome::Replaced && Self::unpack_weight(entry) < target_weight {
+                        target = i;
+                        target_weight = Self::unpack_weight(entry);
+                    }
+                }
+                entries[bucket + target].store(Self::pack(packed_key, best_move, weight, value), Ordering::Relaxed);
+                Self::collided(outcome, shared)
+            },
+            Entries::Wide(entries) => {
+                let key = key.as_u128();
+                let mut target = 0;
+                let mut target_weight = u8::MAX;
+                let mut outcome = PutOutcome::Replaced;
+                let mut shared = false;
+                let mut best_move = best_move.map_or(Self::NO_MOVE, u64::from);
+                for (i, entry) in entries[bucket..bucket + Self::BUCKET_SIZE].iter().enumerate() {
+                    // an entry being written is left to its writer
+                    let Some((entry_key, state)) = entry.read() else {
+                        shared = true;
+                        continue;
+                    };
+                    if entry_key == key {
+                        target = i;
+                        outcome = PutOutcome::Updated;
+                        if best_move == Self::NO_MOVE {
+                            best_move = Self::unpack_move(state);
+                        }
+                        break;
+                    }
+                    if entry_key == WideEntry::EMPTY_KEY {
+                        if outcome == PutOutcome::Replaced {
+                            target = i;
+                            outcome = PutOutcome::Inserted;
+                        }
+                        continue;
+                    }
+                    shared = true;
+                    if outcome == PutOutcome::Replaced && Self::unpack_weight(state) < target_weight {
+                        target = i;
+                        target_weight = Self::unpack_weight(state);
+                    }
+                }
+                entries[bucket + target].write(key, best_move, weight, value);
+                Self::collided(outcome, shared)
+            },
+        }
+    }
+
+    pub fn get<B: BoardBits>(&self, key: B) -> Option<u8> {
+        self.probe(key).map(|entry| entry.value)
+    }
+
+    pub fn probe<B: BoardBits>(&self, key: B) -> Option<Entry> {
+        let bucket = self.bucket(key);
+        match &self.entries {
+            Entries::Packed(entries) => {
+                let packed_key = Self::packed_key(key);
+                entries[bucket..bucket + Self::BUCKET_SIZE].iter()
+                    .map(|entry| entry.load(Ordering::Relaxed))
+                    .find(|entry| entry >> Self::PACKED_KEY_SHIFT == packed_key)
+                    .map(Self::unpack_entry)
+            },
+            Entries::Wide(entries) => {
+                let key = key.as_u128();
+                entries[bucket..bucket + Self::BUCKET_SIZE].iter()
+                    .filter_map(WideEntry::read)
+                    .find(|(entry_key, _)| *entry_key == key)
+                    .map(|(_, state)| Self::unpack_entry(state))
+            },
         }
-        Some(self.values[index])
     }
 
     pub fn utilization(&self) -> (usize, usize) {
-        (self.values.iter()
-            .filter(|v| **v != 0)
-            .count(),
-        Self::SIZE)
+        let used = match &self.entries {
+            Entries::Packed(entries) => entries.iter()
+                .filter(|entry| entry.load(Ordering::Relaxed) != Self::EMPTY)
+                .count(),
+            Entries::Wide(entries) => entries.iter()
+                .filter(|entry| entry.key() != WideEntry::EMPTY_KEY)
+                .count(),
+        };
+        (used, self.buckets * Self::BUCKET_SIZE)
+    }
+
+    // index of the first entry in the key's bucket
+    fn bucket<B: BoardBits>(&self, key: B) -> usize {
+        key.rem_u64(self.buckets as u64) as usize * Self::BUCKET_SIZE
+    }
+
+    fn collided(outcome: PutOutcome, shared: bool) -> PutOutcome {
+        if outcome == PutOutcome::Inserted && shared {
+            PutOutcome::Collided
+        }
+        else {
+            outcome
+        }
+    }
+
+    // packed entries are used whenever they can hold keys exactly
+    fn layout(dimensions: Dimensions, memory_bytes: usize) -> (usize, bool) {
+        let packed_buckets = Self::buckets_for(memory_bytes, size_of::<AtomicU64>());
+        if Self::covers(dimensions.bits(), Self::PACKED_KEY_BITS, packed_buckets) {
+            (packed_buckets, true)
+        }
+        else {
+            (Self::buckets_for(memory_bytes, size_of::<WideEntry>()), false)
+        }
+    }
+
+    fn buckets_for(memory_bytes: usize, entry_size: usize) -> usize {
+        get_prime_less_than_or_equal((memory_bytes / (entry_size * Self::BUCKET_SIZE)) as u64) as usize
     }
 
-    fn index(key: BoardType) -> usize {
-        (key % Self::SIZE as BoardType) as usize
+    fn packed_key<B: BoardBits>(key: B) -> u64 {
+        key.low_u64() & ((1 << Self::PACKED_KEY_BITS) - 1)
     }
+
+    fn pack(packed_key: u64, best_move: u64, weight: u8, value: u8) -> u64 {
+        (packed_key << Self::PACKED_KEY_SHIFT)
+            | best_move << (Self::WEIGHT_BITS + Self::VALUE_BITS)
+            | (weight as u64) << Self::VALUE_BITS
+            | value as u64
+    }
+
+    fn unpack_entry(entry: u64) -> Entry {
+        Entry {
+            value: entry as u8,
+            best_move: Some(Self::unpack_move(entry))
+                .filter(|&best_move| best_move != Self::NO_MOVE)
+                .map(|best_move| best_move as u8),
+        }
+    }
+
+    fn unpack_move(entry: u64) -> u64 {
+        (entry >> (Self::WEIGHT_BITS + Self::VALUE_BITS)) & Self::NO_MOVE
+    }
+
+    fn unpack_weight(entry: u64) -> u8 {
+        (entry >> Self::VALUE_BITS) as u8
+    }
+
+    fn covers(position_key_bits: u32, table_key_bits: u32, buckets: usize) -> bool {
+        position_key_bits <= table_key_bits
+            || (1u128 << (position_key_bits - table_key_bits)) <= buckets as u128
+    }
+}
+
+impl Entries {
+    fn key_bits(&self) -> u32 {
+        match self {
+            Entries::Packed(_) => TranspositionTable::PACKED_KEY_BITS,
+            Entries::Wide(_) => u128::BITS,
+        }
+    }
+}
+
+fn bincode_config() -> Configuration {
+    config::standard()
 }
 
-fn get_prime_greater_than_or_equal(n: BoardType) -> BoardType {
-    let mut composite_map: HashMap<BoardType, Vec<BoardType>> = HashMap::new();
-    let mut number: BoardType = 2;
+fn get_prime_greater_than_or_equal(n: u64) -> u64 {
+    let mut composite_map: HashMap<u64, Vec<u64>> = HashMap::new();
+    let mut number: u64 = 2;
 
     loop {
         match composite_map.remove(&number) {
@@ -85,19 +452,48 @@ fn get_prime_greater_than_or_equal(n: BoardType) -> BoardType {
     }
 }
 
-fn is_prime(n: BoardType) -> bool {
+// never smaller than 3, so the table always has a few entries
+fn get_prime_less_than_or_equal(n: u64) -> u64 {
+    (3..=n.max(3))
+        .rev()
+        .find(|number| is_prime(*number))
+        .unwrap_or(3)
+}
+
+// accepts a plain number of bytes or a number followed by KB, MB or GB
+pub fn parse_memory(s: &str) -> Result<usize, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let (number, multiplier) = if let Some(number) = upper.strip_suffix("GB") {
+        (number, 1 << 30)
+    }
+    else if let Some(number) = upper.strip_suffix("MB") {
+        (number, 1 << 20)
+    }
+    else if let Some(number) = upper.strip_suffix("KB") {
+        (number, 1 << 10)
+    }
+    else {
+        (upper.strip_suffix('B').unwrap_or(upper.as_str()), 1)
+    };
+    match number.trim().parse::<usize>() {
+        Ok(number) => number.checked_mul(multiplier).ok_or(format!("'{}' is too large", s)),
+        Err(_) => Err(format!("'{}' is not a valid memory size, expected e.g. 512MB", s)),
+    }
+}
+
+fn is_prime(n: u64) -> bool {
     if n <= 1 {
         return false;
     }
-    let mut potential_factor: BoardType = 2;
-    let stop_at: BoardType = (n as f64).sqrt() as BoardType + 1;
+    let mut potential_factor: u64 = 2;
+    let stop_at: u64 = (n as f64).sqrt() as u64 + 1;
 
     while potential_factor <= stop_at {
-        if n % potential_factor == 0 {
+        if n.is_multiple_of(potential_factor) {
             return false;
         }
         potential_factor += 1 + potential_factor % 2;
     }
 
-    return true;
-}
\ No newline at end of file
+    true
+}
@@ -1,7 +1,14 @@
 #[cfg(test)]
 mod integration {
     use connect4::{position_factory, reader};
-    use connect4::solver::{Solver, SolverOptions};
+    use connect4::position_factory::AnyBitBoard;
+    use connect4::bit_board::BitBoard;
+    use connect4::position::{BoardBits, Dimensions, GameStatus, Player, Position};
+    use connect4::engine::{Engine, Strength, play_match};
+    use connect4::opening_book::OpeningBook;
+    use connect4::solver::{Cancelled, Progress, SearchLimits, Solver, SolverOptions};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     #[test]
     fn test_easy_end_games() {
@@ -63,9 +70,499 @@ mod integration {
         test_file("./data/Test_Start_Hard", true, 0, 100);
     }
 
+    #[test]
+    fn test_small_board_draw() {
+        let dimensions = Dimensions::new(4, 4).unwrap();
+        let mut solver = Solver::new(Some(SolverOptions {
+            dimensions,
+            ..SolverOptions::default()
+        }));
+        let position = position_factory::create_with_dimensions::<u64>("", dimensions).unwrap();
+        assert_eq!(0, solver.solve(position));
+    }
+
+    #[test]
+    fn test_u128_storage_matches_u64() {
+        let mut solver = Solver::new(None);
+        for (moves, expected) in read_tests("./data/Test_Mid_Easy", 100, 0) {
+            let position = position_factory::create_with_dimensions::<u128>(moves.as_str(), Dimensions::STANDARD).unwrap();
+            assert_eq!(expected, solver.solve(position));
+        }
+    }
+
+    #[test]
+    fn test_principal_variation_reaches_score() {
+        let mut solver = Solver::new(None);
+        for (moves, expected) in read_tests("./data/Test_End_Easy", 100, 0) {
+            let mut position = position_factory::create(moves.as_str()).unwrap();
+            let (score, principal_variation) = solver.solve_with_pv(position.clone());
+            assert_eq!(expected, score);
+
+            let player = Player::to_move(position.get_move_count());
+            for col in principal_variation {
+                position.play(col);
+            }
+            let board_size = position.dimensions().board_size();
+            let winning_score = ((board_size + 2 - position.get_move_count()) / 2) as i8;
+            match position.status() {
+                GameStatus::Win { player: winner, .. } if winner == player => assert_eq!(winning_score, score),
+                GameStatus::Win { .. } => assert_eq!(-winning_score, score),
+                GameStatus::Draw => assert_eq!(0, score),
+                GameStatus::Ongoing => panic!("{} did not reach the end of the game", moves),
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_line_is_playable() {
+        let mut solver = Solver::new(None);
+        for (moves, expected) in read_tests("./data/Test_End_Easy", 100, 0) {
+            let mut position = position_factory::create(moves.as_str()).unwrap();
+            assert_eq!(expected, solver.solve(position.clone()));
+            let line = solver.table_line(position.clone());
+            if expected > 0 && !position.can_win_next_move() {
+                assert!(!line.is_empty(), "{} has no stored move", moves);
+            }
+            for col in line {
+                assert!(position.can_play(col));
+                position.play(col);
+            }
+        }
+    }
+
+    #[test]
+    fn test_node_limited_search_returns_bounds() {
+        let mut solver = Solver::new(None);
+        for (moves, expected) in read_tests("./data/Test_Start_Hard", 5, 0) {
+            let position = position_factory::create(moves.as_str()).unwrap();
+            let result = solver.solve_limited(position.clone(), SearchLimits { nodes: Some(10_000), ..SearchLimits::default() }).unwrap();
+            assert!(result.min <= expected && expected <= result.max);
+            assert!(position.can_play(result.best_move.unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_limited_search_moves_are_proven() {
+        let mut solver = Solver::new(None);
+        for ((moves, _), nodes) in read_tests("./data/Test_Mid_Medium", 20, 0).zip([100, 2_000].into_iter().cycle()) {
+            let mut position = position_factory::create(moves.as_str()).unwrap();
+            let result = Solver::new(None).solve_limited(position.clone(), SearchLimits { nodes: Some(nodes), ..SearchLimits::default() }).unwrap();
+            // no null window was beaten, so no move is proven, but there is still one to play
+            if result.min == position.min_possible_score() {
+                assert!(!result.best_move_proven);
+            }
+            let col = result.best_move.unwrap();
+            assert!(position.can_play(col));
+            if result.best_move_proven && !position.is_winning_move(col) {
+                position.play(col);
+                assert!(-solver.solve(position) >= result.min, "{} is not proven for {}", col, moves);
+            }
+        }
+    }
+
+    #[test]
+    fn test_limited_best_move() {
+        let mut solver = Solver::new(None);
+        let position = position_factory::create("33").unwrap();
+        let result = solver.solve_limited(position.clone(), SearchLimits { nodes: Some(5_000), ..SearchLimits::default() }).unwrap();
+        assert!(result.score().is_none());
+        assert!(position.can_play(result.best_move.unwrap()));
+
+        let col = solver.best_move_limited(position.clone(), SearchLimits { nodes: Some(5_000), ..SearchLimits::default() }).unwrap();
+        assert!(position.can_play(col as u8));
+
+        let (moves, _) = read_tests("./data/Test_Mid_Easy", 1, 0).next().unwrap();
+        let position = position_factory::create(moves.as_str()).unwrap();
+        let expected = Solver::new(None).analyze(position.clone());
+        let col = solver.best_move_limited(position, SearchLimits { time: Some(Duration::from_secs(60)), ..SearchLimits::default() }).unwrap();
+        assert_eq!(expected.iter().max(), expected.get(col));
+    }
+
+    #[test]
+    fn test_time_limited_search_completes() {
+        let mut solver = Solver::new(None);
+        for (moves, expected) in read_tests("./data/Test_Mid_Easy", 20, 0) {
+            let mut position = position_factory::create(moves.as_str()).unwrap();
+            let result = solver.solve_limited(position.clone(), SearchLimits { time: Some(Duration::from_secs(60)), ..SearchLimits::default() }).unwrap();
+            assert_eq!(Some(expected), result.score());
+
+            let best_move = result.best_move.unwrap();
+            if !position.is_winning_move(best_move) {
+                position.play(best_move);
+                assert_eq!(expected, -solver.solve(position));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancelled_solve_leaves_solver_usable() {
+        let mut solver = Solver::new(None);
+        let (moves, expected) = read_tests("./data/Test_Mid_Medium", 1, 0).next().unwrap();
+        let position = position_factory::create(moves.as_str()).unwrap();
+
+        // cancels after the first iteration of the search
+        let handle = solver.cancel_handle();
+        solver.set_progress_callback(Some(Box::new(move |_: &Progress| handle.cancel())));
+        let Cancelled(bounds) = solver.try_solve(position.clone()).unwrap_err();
+        assert!(bounds.min <= expected && expected <= bounds.max);
+
+        solver.set_progress_callback(None);
+        assert_eq!(Ok(expected), solver.try_solve(position));
+    }
+
+    #[test]
+    fn test_cancel_after_search_does_not_cancel_next() {
+        let mut solver = Solver::new(None);
+        let handle = solver.cancel_handle();
+        let mut tests = read_tests("./data/Test_Mid_Medium", 2, 0);
+        let (moves, expected) = tests.next().unwrap();
+        assert_eq!(Ok(expected), solver.try_solve(position_factory::create(moves.as_str()).unwrap()));
+        handle.cancel();
+        // a position the table does not answer, so that the search checks for a cancel
+        let (moves, expected) = tests.next().unwrap();
+        assert_eq!(Ok(expected), solver.try_solve(position_factory::create(moves.as_str()).unwrap()));
+        assert!(solver.stats().nodes > 1 << 12);
+    }
+
+    #[test]
+    fn test_cancel_from_another_thread() {
+        let mut solver = Solver::new(None);
+        let handle = solver.cancel_handle();
+        let (moves, expected) = read_tests("./data/Test_Mid_Medium", 1, 1).next().unwrap();
+        let position = position_factory::create(moves.as_str()).unwrap();
+
+        // the worker is handed the solver, but only starts solving after the cancel
+        let (start, started) = std::sync::mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            started.recv().unwrap();
+            let result = solver.try_solve(position);
+            (solver, result)
+        });
+        handle.cancel();
+        start.send(()).unwrap();
+        let (mut solver, result) = worker.join().unwrap();
+        let Cancelled(bounds) = result.unwrap_err();
+        assert!(bounds.min <= expected && expected <= bounds.max);
+
+        let position = position_factory::create(moves.as_str()).unwrap();
+        assert_eq!(expected, solver.solve(position));
+    }
+
+    #[test]
+    fn test_search_stats_and_progress() {
+        let mut solver = Solver::new(None);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let callback_reports = reports.clone();
+        solver.set_progress_callback(Some(Box::new(move |progress: &Progress|
+            callback_reports.lock().unwrap().push((progress.min, progress.max, progress.stats.nodes))
+        )));
+
+        let (moves, expected) = read_tests("./data/Test_Start_Medium", 1, 0).next().unwrap();
+        let position = position_factory::create(moves.as_str()).unwrap();
+        assert_eq!(expected, solver.solve(position));
+
+        let stats = solver.stats();
+        let reports = reports.lock().unwrap();
+        assert_eq!(stats.iterations as usize, reports.len());
+        assert_eq!(Some(&(expected, expected, stats.nodes)), reports.last());
+        assert!(stats.nodes >= stats.table_hits + stats.table_misses);
+        assert!(stats.table_misses > 0);
+    }
+
+    #[test]
+    fn test_small_transposition_table() {
+        let mut solver = Solver::new(Some(SolverOptions {
+            table_memory: 64 << 10,
+            ..SolverOptions::default()
+        }));
+        for (moves, expected) in read_tests("./data/Test_Mid_Medium", 20, 0) {
+            let position = position_factory::create(moves.as_str()).unwrap();
+            assert_eq!(expected, solver.solve(position));
+        }
+    }
+
+    #[test]
+    fn test_saved_table_is_reused() {
+        let options = SolverOptions { table_memory: 1 << 20, ..SolverOptions::default() };
+        let filepath = std::env::temp_dir().join(format!("connect4_table_{}", std::process::id()));
+        let filepath = filepath.to_str().unwrap();
+        let tests: Vec<_> = read_tests("./data/Test_Mid_Easy", 20, 0).collect();
+
+        let mut solver = Solver::new(Some(options.clone()));
+        let mut nodes = 0;
+        for (moves, expected) in tests.iter() {
+            assert_eq!(*expected, solver.solve(position_factory::create(moves.as_str()).unwrap()));
+            nodes += solver.stats().nodes;
+        }
+        solver.save_table(filepath).unwrap();
+
+        let mut reloaded = Solver::new(Some(options.clone()));
+        reloaded.load_table(filepath).unwrap();
+        let mut reloaded_nodes = 0;
+        for (moves, expected) in tests.iter() {
+            assert_eq!(*expected, reloaded.solve(position_factory::create(moves.as_str()).unwrap()));
+            reloaded_nodes += reloaded.stats().nodes;
+        }
+        assert!(reloaded_nodes < nodes);
+
+        let mut resized = Solver::new(Some(SolverOptions { table_memory: 2 << 20, ..options.clone() }));
+        assert!(resized.load_table(filepath).is_err());
+        let mut other_board = Solver::new(Some(SolverOptions { dimensions: Dimensions::new(6, 5).unwrap(), ..options.clone() }));
+        assert!(other_board.load_table(filepath).is_err());
+        std::fs::remove_file(filepath).unwrap();
+    }
+
+    #[test]
+    fn test_chained_opening_books() {
+        let options = SolverOptions { opening_books: Some(Vec::new()), ..SolverOptions::default() };
+        let (moves, expected) = read_tests("./data/Test_Start_Easy", 1, 0).next().unwrap();
+        let mut position = position_factory::create(moves.as_str()).unwrap();
+
+        let mut solver = Solver::new(Some(options.clone()));
+        assert_eq!(expected, solver.solve(position.clone()));
+        assert_eq!(0, solver.stats().book_hits);
+        let nodes = solver.stats().nodes;
+
+        let mut book = OpeningBook::new(Dimensions::STANDARD);
+        for col in 0..7 {
+            if position.can_play(col) && !position.is_winning_move(col) {
+                position.play(col);
+                book.extend([(position.symmetric_key(), solver.solve(position.clone()))]);
+                position.undo(col);
+            }
+        }
+        let mut booked = Solver::new(Some(options));
+        booked.add_opening_book(OpeningBook::new(Dimensions::STANDARD));
+        booked.add_opening_book(book);
+        assert_eq!(expected, booked.solve(position));
+        assert!(booked.stats().book_hits > 0);
+        assert!(booked.stats().nodes < nodes);
+    }
+
+    #[test]
+    fn test_root_position_in_book() {
+        let options = SolverOptions { opening_books: Some(Vec::new()), ..SolverOptions::default() };
+        for (moves, expected) in read_tests("./data/Test_Mid_Medium", 5, 0) {
+            let mut position = position_factory::create(moves.as_str()).unwrap();
+            let mut book = OpeningBook::new(Dimensions::STANDARD);
+            book.extend([(position.symmetric_key(), expected)]);
+            let mut solver = Solver::new(Some(options.clone()));
+            solver.add_opening_book(book);
+
+            let result = solver.solve_limited(position.clone(), SearchLimits::default()).unwrap();
+            assert_eq!(Some(expected), result.score());
+            assert_eq!(1, solver.stats().iterations);
+            assert_eq!(1, solver.stats().book_hits);
+            let best_move = result.best_move.unwrap();
+            if !position.is_winning_move(best_move) {
+                position.play(best_move);
+                assert_eq!(expected, -Solver::new(Some(options.clone())).solve(position));
+            }
+        }
+    }
+
+    #[test]
+    fn test_engine_strength() {
+        let positions: Vec<_> = read_tests("./data/Test_Mid_Easy", 20, 0)
+            .map(|(moves, _)| position_factory::create(moves.as_str()).unwrap())
+            .collect();
+
+        let mut perfect = Engine::new(Solver::new(None), Strength::PERFECT, 1);
+        let mut solver = Solver::new(None);
+        for position in positions.iter() {
+            let scores = solver.analyze(position.clone());
+            let best = scores.iter().flatten().max().copied();
+            let col = perfect.choose_move(position.clone()).unwrap();
+            assert_eq!(best, scores[col as usize]);
+        }
+
+        let play = |seed| {
+            let mut engine = Engine::new(Solver::new(None), Strength::EASY, seed);
+            positions.iter().map(|position| engine.choose_move(position.clone()).unwrap()).collect::<Vec<u8>>()
+        };
+        let moves = play(7);
+        assert_eq!(moves, play(7));
+        let suboptimal = positions.iter().zip(moves.iter()).filter(|(position, col)| {
+            let scores = solver.analyze((*position).clone());
+            scores[**col as usize] < scores.iter().flatten().max().copied()
+        }).count();
+        assert!(suboptimal > 0);
+    }
+
+    #[test]
+    fn test_match_between_engines() {
+        let dimensions = Dimensions::new(4, 4).unwrap();
+        let options = SolverOptions { dimensions, table_memory: 1 << 20, ..SolverOptions::default() };
+        let engine = |strength, seed| Engine::new(Solver::new(Some(options.clone())), strength, seed);
+        let openings = [position_factory::create_with_dimensions::<u64>("", dimensions).unwrap()];
+
+        let (mut first, mut second) = (engine(Strength::PERFECT, 0), engine(Strength::PERFECT, 0));
+        let stats = play_match([&mut first, &mut second], &openings, 4);
+        assert_eq!((0, 4, 0), (stats.wins, stats.draws, stats.losses));
+        assert_eq!(stats.moves[0] + stats.moves[1], 4 * 16);
+
+        let (mut easy, mut perfect) = (engine(Strength::EASY, 3), engine(Strength::PERFECT, 0));
+        let stats = play_match([&mut easy, &mut perfect], &openings, 10);
+        assert_eq!(10, stats.wins + stats.draws + stats.losses);
+        assert_eq!(0, stats.wins);
+    }
+
+    #[test]
+    fn test_wide_board_end_games() {
+        for dimensions in [Dimensions::new(8, 7).unwrap(), Dimensions::new(9, 7).unwrap()] {
+            let mut solver = Solver::new(Some(SolverOptions {
+                dimensions,
+                ..SolverOptions::default()
+            }));
+            let mut seed = 7;
+            for _ in 0..20 {
+                let position = random_end_game::<u128>(dimensions, 12, &mut seed);
+                assert_eq!(brute_force(&position), solver.solve(position.clone()), "{}", position.stringify());
+            }
+        }
+    }
+
+    #[test]
+    fn test_storage_follows_dimensions() {
+        let (moves, expected) = read_tests("./data/Test_End_Easy", 1, 0).next().unwrap();
+        let Ok(AnyBitBoard::Narrow(position)) = position_factory::create_auto(moves.as_str(), Dimensions::STANDARD) else {
+            panic!("a 7x6 board fits in a u64");
+        };
+        assert_eq!(expected, Solver::new(None).solve(position));
+
+        let mut seed = 11;
+        for dimensions in [Dimensions::new(8, 7).unwrap(), Dimensions::new(9, 7).unwrap()] {
+            let mut solver = Solver::new(Some(SolverOptions { dimensions, ..SolverOptions::default() }));
+            let board = random_end_game::<u128>(dimensions, 12, &mut seed);
+            // a board string, the player up next followed by the cells
+            let up_next = if board.get_move_count().is_multiple_of(2) { '1' } else { '2' };
+            let cells: String = String::from(&board).split_whitespace().collect();
+            let position = position_factory::create_auto(format!("{}{}", up_next, cells).as_str(), dimensions).unwrap();
+            assert_eq!(dimensions, position.dimensions());
+            let score = match position {
+                AnyBitBoard::Narrow(position) => {
+                    assert!(dimensions.fits::<u64>());
+                    solver.solve(position)
+                },
+                AnyBitBoard::Wide(position) => {
+                    assert!(!dimensions.fits::<u64>());
+                    solver.solve(position)
+                },
+            };
+            assert_eq!(brute_force(&board), score);
+        }
+    }
+
+    // boards that fill their storage, where shifts lose the top columns
+    // and every table value of the 128 bit boards is outside the range of an i8
+    #[test]
+    fn test_largest_boards_end_games() {
+        largest_board_end_games::<u64>(Dimensions::new(8, 7).unwrap());
+        largest_board_end_games::<u128>(Dimensions::new(4, 31).unwrap());
+        largest_board_end_games::<u128>(Dimensions::new(10, 11).unwrap());
+    }
+
+    fn largest_board_end_games<B: BoardBits>(dimensions: Dimensions) {
+        let mut solver = Solver::new(Some(SolverOptions {
+            dimensions,
+            opening_books: Some(Vec::new()),
+            ..SolverOptions::default()
+        }));
+        let mut seed = 11;
+        for _ in 0..10 {
+            let position = random_end_game::<B>(dimensions, 12, &mut seed);
+            assert_eq!(brute_force(&position), solver.solve(position.clone()), "{}", position.stringify());
+        }
+    }
+
+    // forced wins this early score near the top of the range, where table values no longer fit an i8
+    #[test]
+    fn test_wide_board_large_scores() {
+        for dimensions in [Dimensions::new(9, 7).unwrap(), Dimensions::new(10, 11).unwrap()] {
+            let mut solver = Solver::new(Some(SolverOptions {
+                dimensions,
+                opening_books: Some(Vec::new()),
+                ..SolverOptions::default()
+            }));
+            let winning_score = ((dimensions.board_size() + 1 - 6) / 2) as i8;
+            for (moves, expected) in [("3040", winning_score), ("30405", -winning_score)] {
+                let position = position_factory::create_with_dimensions::<u128>(moves, dimensions).unwrap();
+                let (score, principal_variation) = solver.solve_with_pv(position.clone());
+                assert_eq!(expected, score, "{} on {}", moves, dimensions);
+                assert_eq!(7 - moves.len(), principal_variation.len(), "{} on {}", moves, dimensions);
+            }
+        }
+    }
+
+    // fills the board with random non-winning moves until only `empty` cells remain
+    fn random_end_game<B: BoardBits>(dimensions: Dimensions, empty: u8, seed: &mut u64) -> BitBoard<B> {
+        'restart: loop {
+            let mut position = BitBoard::<B>::with_dimensions(dimensions);
+            while position.get_move_count() < dimensions.board_size() - empty {
+                let candidates: Vec<u8> = (0..dimensions.width)
+                    .filter(|col| position.can_play(*col) && !position.is_winning_move(*col))
+                    .collect();
+                if candidates.is_empty() {
+                    continue 'restart;
+                }
+                *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                position.play(candidates[(*seed >> 33) as usize % candidates.len()]);
+            }
+            if !position.can_win_next_move() {
+                return position;
+            }
+        }
+    }
+
+    fn brute_force<B: BoardBits>(position: &BitBoard<B>) -> i8 {
+        if position.get_move_count() == position.dimensions().board_size() {
+            return 0;
+        }
+        let mut best = i8::MIN;
+        for col in 0..position.dimensions().width {
+            if !position.can_play(col) {
+                continue;
+            }
+            if position.is_winning_move(col) {
+                return position.max_possible_score();
+            }
+            let mut position2 = position.clone();
+            position2.play(col);
+            best = best.max(-brute_force(&position2));
+        }
+        best
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        // every test file, the hard starts are the two cheapest of the first few
+        for (filepath, limit, skip) in [
+            ("./data/Test_End_Easy", 200, 0),
+            ("./data/Test_Mid_Easy", 200, 0),
+            ("./data/Test_Mid_Medium", 50, 0),
+            ("./data/Test_Start_Easy", 200, 0),
+            ("./data/Test_Start_Medium", 5, 0),
+            ("./data/Test_Start_Hard", 1, 2),
+            ("./data/Test_Start_Hard", 1, 5),
+        ] {
+            test_file_with_threads(filepath, false, limit, skip, 4);
+        }
+    }
+
+    #[test]
+    fn test_parallel_matches_serial_weak() {
+        test_file_with_threads("./data/Test_Start_Medium", true, 20, 0, 4);
+    }
+
     fn test_file(filepath: &str, weak: bool, limit: usize, skip: usize) {
+        test_file_with_threads(filepath, weak, limit, skip, 1);
+    }
+
+    fn test_file_with_threads(filepath: &str, weak: bool, limit: usize, skip: usize, threads: usize) {
         let mut solver = Solver::new(Some(SolverOptions {
             weak,
+            threads,
+            ..SolverOptions::default()
         }));
         for (moves, expected) in read_tests(filepath, limit, skip) {
             let position = position_factory::create(moves.as_str()).unwrap();
@@ -1,7 +1,12 @@
 #[cfg(test)]
 mod unit {
-    use connect4::position::Position;
-    use connect4::position_factory;
+    use connect4::position::{Position, Dimensions, GameStatus, Player};
+    use connect4::{position_factory, reader};
+    use connect4::cli::{Args, CliError, Command, Flag, OutputFormat};
+    use connect4::engine::{Rng, Strength};
+    use std::io::Write;
+    use connect4::opening_book::{BookError, BookReport, OpeningBook, load_opening_book, save_opening_book};
+    use connect4::transposition_table::{self, Entry, PutOutcome, TranspositionTable};
 
     #[test]
     fn test_vertical_win() {
@@ -26,4 +31,407 @@ mod unit {
         let position = position_factory::create("6554434331").unwrap();
         assert!(position.is_winning_move(3));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_dimensions() {
+        assert_eq!(Ok(Dimensions { width: 8, height: 7 }), "8x7".parse::<Dimensions>());
+        assert!("11x6".parse::<Dimensions>().is_err());
+        assert!("7by6".parse::<Dimensions>().is_err());
+        // the largest boards that fit in 128 bits
+        assert!(Dimensions::new(4, 31).is_ok());
+        assert!(Dimensions::new(10, 11).is_ok());
+        assert!(Dimensions::new(4, 32).is_err());
+        assert!(Dimensions::new(10, 12).is_err());
+    }
+
+    #[test]
+    fn test_custom_dimensions_win() {
+        let dimensions = Dimensions::new(6, 5).unwrap();
+        let position = position_factory::create_with_dimensions::<u64>("001122", dimensions).unwrap();
+        assert!(position.is_winning_move(3));
+        assert!(!position.is_winning_move(5));
+    }
+
+    #[test]
+    fn test_custom_dimensions_stringify() {
+        let dimensions = Dimensions::new(5, 4).unwrap();
+        let position = position_factory::create_with_dimensions::<u64>("0044", dimensions).unwrap();
+        assert_eq!("0 0 0 0 0 \n0 0 0 0 0 \n2 0 0 0 2 \n1 0 0 0 1 \n", position.stringify());
+    }
+
+    #[test]
+    fn test_custom_dimensions_symmetric_key() {
+        let dimensions = Dimensions::new(8, 7).unwrap();
+        let position = position_factory::create_with_dimensions::<u128>("0127", dimensions).unwrap();
+        let mirrored = position_factory::create_with_dimensions::<u128>("7650", dimensions).unwrap();
+        assert_ne!(position.key(), mirrored.key());
+        assert_eq!(position.symmetric_key(), mirrored.symmetric_key());
+    }
+
+    #[test]
+    fn test_board_too_large_for_storage() {
+        let dimensions = Dimensions::new(9, 7).unwrap();
+        assert!(position_factory::create_with_dimensions::<u64>("", dimensions).is_err());
+        assert!(position_factory::create_with_dimensions::<u128>("", dimensions).is_ok());
+    }
+
+    #[test]
+    fn test_wide_transposition_table_keys() {
+        let dimensions = Dimensions::new(9, 7).unwrap();
+        let table = TranspositionTable::new(dimensions);
+        let key: u128 = 12345;
+        // same low 64 bits, but different positions
+        let colliding_keys: Vec<u128> = (1..16).map(|i| key + (i << 64)).collect();
+        table.put(key, 3, 0, None);
+        assert_eq!(Some(3), table.get(key));
+        assert!(colliding_keys.iter().all(|&colliding_key| table.get(colliding_key).is_none()));
+        for (value, &colliding_key) in colliding_keys.iter().enumerate() {
+            table.put(colliding_key, value as u8, 0, None);
+        }
+        for (value, &colliding_key) in colliding_keys.iter().enumerate() {
+            assert!(table.get(colliding_key).is_none_or(|v| v == value as u8));
+        }
+    }
+
+    #[test]
+    fn test_bucket_keeps_heaviest_entries() {
+        let table = TranspositionTable::with_memory(Dimensions::STANDARD, 64 * 1024);
+        let (_, entries) = table.utilization();
+        let buckets = entries as u64 / 4;
+        // every key lands in bucket 0
+        let keys: Vec<u64> = (1..=5).map(|i| i * buckets).collect();
+        assert_eq!(PutOutcome::Inserted, table.put(keys[0], 1, 30, None));
+        assert_eq!(PutOutcome::Collided, table.put(keys[1], 2, 10, None));
+        assert_eq!(PutOutcome::Collided, table.put(keys[2], 3, 40, None));
+        assert_eq!(PutOutcome::Collided, table.put(keys[3], 4, 20, None));
+        assert_eq!(PutOutcome::Updated, table.put(keys[3], 5, 20, None));
+        assert_eq!(PutOutcome::Replaced, table.put(keys[4], 6, 5, None));
+        assert_eq!(None, table.get(keys[1]));
+        assert_eq!(Some(1), table.get(keys[0]));
+        assert_eq!(Some(3), table.get(keys[2]));
+        assert_eq!(Some(5), table.get(keys[3]));
+        assert_eq!(Some(6), table.get(keys[4]));
+    }
+
+    #[test]
+    fn test_table_stores_best_move() {
+        for dimensions in [Dimensions::STANDARD, Dimensions::new(9, 7).unwrap()] {
+            let table = TranspositionTable::new(dimensions);
+            let key: u128 = 12345;
+            table.put(key, 7, 20, Some(3));
+            assert_eq!(Some(Entry { value: 7, best_move: Some(3) }), table.probe(key));
+            // an upper bound has no best move, so the previous one is kept
+            table.put(key, 2, 20, None);
+            assert_eq!(Some(Entry { value: 2, best_move: Some(3) }), table.probe(key));
+            table.put(key + 1, 250, 20, None);
+            assert_eq!(Some(Entry { value: 250, best_move: None }), table.probe(key + 1));
+        }
+    }
+
+    #[test]
+    fn test_wide_table_shared_between_threads() {
+        let table = TranspositionTable::with_memory(Dimensions::new(9, 7).unwrap(), 4096);
+        // every key has its own value and move, so an entry mixing two writes would show
+        let entry = |key: u128| Entry { value: (key % 251) as u8, best_move: Some((key % 9) as u8) };
+        std::thread::scope(|scope| {
+            for thread in 0..4u128 {
+                let table = &table;
+                scope.spawn(move || {
+                    let mut key = thread;
+                    for _ in 0..20_000 {
+                        key = key.wrapping_mul(0x2545_F491_4F6C_DD1D_6A09_E667_F3BC_C909).wrapping_add(1) >> 1;
+                        let Entry { value, best_move } = entry(key);
+                        table.put(key, value, (key % 64) as u8, best_move);
+                        let probed = key.wrapping_mul(31) >> 1;
+                        assert!(table.probe(probed).is_none_or(|found| found == entry(probed)));
+                        assert!(table.probe(key).is_none_or(|found| found == entry(key)));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_opening_book_round_trip() {
+        let dimensions = Dimensions::new(5, 4).unwrap();
+        let filepath = std::env::temp_dir().join(format!("connect4_book_{}", std::process::id()));
+        let missing = std::env::temp_dir().join(format!("connect4_missing_book_{}", std::process::id()));
+        assert!(matches!(load_opening_book(&missing, dimensions), Err(BookError::Missing(_))));
+
+        let mut book = OpeningBook::new(dimensions);
+        book.extend([(12345u64, 7)]);
+        save_opening_book(&book, &filepath).unwrap();
+        let loaded = load_opening_book(&filepath, dimensions).unwrap();
+        assert_eq!(Some(7), loaded.get(12345u64));
+
+        let wrong_dimensions = load_opening_book(&filepath, Dimensions::new(4, 5).unwrap());
+        assert!(matches!(wrong_dimensions, Err(BookError::WrongDimensions { found: (5, 4), .. })));
+
+        let mut encoded = std::fs::read(&filepath).unwrap();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        std::fs::write(&filepath, &encoded).unwrap();
+        let corrupt = load_opening_book(&filepath, dimensions);
+        std::fs::remove_file(&filepath).unwrap();
+        assert!(matches!(corrupt, Err(BookError::ChecksumMismatch(_))));
+    }
+
+    #[test]
+    fn test_opening_book_keeps_every_position() {
+        let mut book = OpeningBook::new(Dimensions::STANDARD);
+        // keys that would share a slot in a hash table
+        let keys: Vec<u64> = (0..1000).map(|i| i * 3_000_017).collect();
+        let report = book.extend(keys.iter().map(|&key| (key, (key % 7) as i8)));
+        assert_eq!(BookReport { stored: 1000, dropped: 0 }, report);
+
+        let report = book.extend([(keys[3], 5), (1, 1), (1, 2)]);
+        assert_eq!(BookReport { stored: 1, dropped: 2 }, report);
+        assert_eq!(1001, book.len());
+        assert!(keys.iter().all(|&key| book.get(key) == Some((key % 7) as i8)));
+        assert_eq!(Some(1), book.get(1u64));
+        assert_eq!(None, book.get(2u64));
+    }
+
+    fn temp_file(name: &str, contents: &str) -> (std::path::PathBuf, std::fs::File) {
+        let filepath = std::env::temp_dir().join(format!("connect4_{}_{}", name, std::process::id()));
+        std::fs::write(&filepath, contents).unwrap();
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&filepath).unwrap();
+        (filepath, file)
+    }
+
+    #[test]
+    fn test_resume_solved_cuts_partial_lines() {
+        let positions: Vec<String> = ["4", "44", "443"].iter().map(|position| position.to_string()).collect();
+        // the last line parses, but lost its newline and possibly digits of its score
+        let (filepath, mut file) = temp_file("resume_solved", "4 0\n44 -1\n443 -1");
+        assert_eq!(2, reader::resume_solved(&mut file, &positions).unwrap());
+        file.write_all(b"443 -12\n").unwrap();
+        assert_eq!("4 0\n44 -1\n443 -12\n", std::fs::read_to_string(&filepath).unwrap());
+
+        // a line for another position ends the solved lines
+        let (filepath, mut file) = temp_file("resume_solved", "4 0\n3 1\n443 -1\n");
+        assert_eq!(1, reader::resume_solved(&mut file, &positions).unwrap());
+        assert_eq!("4 0\n", std::fs::read_to_string(&filepath).unwrap());
+        std::fs::remove_file(&filepath).unwrap();
+    }
+
+    #[test]
+    fn test_resume_checkpoint_cuts_partial_lines() {
+        let (filepath, mut file) = temp_file("resume_checkpoint", "44 -1\n4 0\n22 -1");
+        let solved = reader::resume_checkpoint(&mut file).unwrap();
+        assert_eq!(2, solved.len());
+        assert_eq!(Some(&0), solved.get("4"));
+        assert_eq!(Some(&-1), solved.get("44"));
+        assert_eq!(None, solved.get("22"));
+        file.write_all(b"22 -12\n").unwrap();
+        assert_eq!("44 -1\n4 0\n22 -12\n", std::fs::read_to_string(&filepath).unwrap());
+
+        // a complete line that does not parse is kept but not taken as solved
+        let (filepath, mut file) = temp_file("resume_checkpoint", "4 0\n22\n");
+        let solved = reader::resume_checkpoint(&mut file).unwrap();
+        assert_eq!(1, solved.len());
+        file.write_all(b"22 -12\n").unwrap();
+        assert_eq!("4 0\n22\n22 -12\n", std::fs::read_to_string(&filepath).unwrap());
+
+        let (filepath, mut file) = temp_file("resume_checkpoint", "");
+        assert!(reader::resume_checkpoint(&mut file).unwrap().is_empty());
+        std::fs::remove_file(&filepath).unwrap();
+    }
+
+    #[test]
+    fn test_read_solved_zip() {
+        let filepath = std::env::temp_dir().join(format!("connect4_solved_{}.zip", std::process::id()));
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&filepath).unwrap());
+        writer.start_file("solved", zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"4455 3\r\n44 -1\r\nnot a line\r\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut lines = Vec::new();
+        reader::read_solved_files(filepath.to_str().unwrap(), |name, contents| {
+            for line in std::io::BufRead::lines(contents) {
+                let line = line.unwrap();
+                lines.push((name.to_string(), reader::parse_position_score(&line).map(|(position, score)| (position.to_string(), score))));
+            }
+        }).unwrap();
+        std::fs::remove_file(&filepath).unwrap();
+
+        let name = format!("{}/solved", filepath.to_str().unwrap());
+        assert_eq!(3, lines.len());
+        assert!(lines.iter().all(|(entry_name, _)| *entry_name == name));
+        assert_eq!(Ok(("4455".to_string(), 3)), lines[0].1);
+        assert_eq!(Ok(("44".to_string(), -1)), lines[1].1);
+        assert!(lines[2].1.is_err());
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        let mut rng = Rng::new(42);
+        let values: Vec<f64> = (0..1000).map(|_| rng.next_f64()).collect();
+        assert!(values.iter().all(|value| (0.0..1.0).contains(value)));
+        let mut same_seed = Rng::new(42);
+        assert!(values.iter().all(|value| *value == same_seed.next_f64()));
+        assert_ne!(Rng::new(42).next_u64(), Rng::new(43).next_u64());
+        assert_eq!(Ok(Strength::EASY), "easy".parse::<Strength>());
+        assert!("impossible".parse::<Strength>().is_err());
+    }
+
+    #[test]
+    fn test_undo_restores_position() {
+        let mut position = position_factory::create("4455").unwrap();
+        let expected = position_factory::create("445").unwrap();
+        position.undo(5);
+        assert_eq!(expected.key(), position.key());
+        assert_eq!(expected.stringify(), position.stringify());
+        assert_eq!(3, position.get_move_count());
+    }
+
+    #[test]
+    fn test_undo_full_column() {
+        let mut position = position_factory::create("000000").unwrap();
+        assert!(!position.can_play(0));
+        position.undo(0);
+        assert!(position.can_play(0));
+        assert!(position.can_undo(0));
+        assert!(!position.can_undo(1));
+        assert_eq!(position_factory::create("00000").unwrap().key(), position.key());
+    }
+
+    #[test]
+    fn test_game_records_moves() {
+        let mut game = position_factory::create_game("3344").unwrap();
+        game.play(5);
+        assert_eq!("33445", game.move_string());
+        assert_eq!(position_factory::create("33445").unwrap().key(), game.key());
+        assert_eq!(Some(5), game.take_back());
+        assert_eq!("3344", game.move_string());
+    }
+
+    #[test]
+    fn test_game_replay() {
+        let game = position_factory::create_game("334455").unwrap();
+        let replayed = game.replay(3).unwrap();
+        assert_eq!("334", replayed.move_string());
+        assert_eq!(position_factory::create("334").unwrap().key(), replayed.key());
+        assert!(game.replay(7).is_none());
+    }
+
+    #[test]
+    fn test_game_rejects_board_strings() {
+        assert!(position_factory::create_game("2000000000000000000000000000000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_status_win() {
+        let position = position_factory::parse("0101010").unwrap();
+        assert_eq!(GameStatus::Win { player: Player::One, line: [(0, 0), (0, 1), (0, 2), (0, 3)] }, position.status());
+    }
+
+    #[test]
+    fn test_status_ongoing_and_draw() {
+        assert_eq!(GameStatus::Ongoing, position_factory::create("444").unwrap().status());
+        let dimensions = Dimensions::new(4, 4).unwrap();
+        let position = position_factory::parse_with_dimensions::<u64>("0123012312301230", dimensions).unwrap();
+        assert_eq!(GameStatus::Draw, position.status());
+    }
+
+    #[test]
+    fn test_rejects_finished_games_to_solve() {
+        assert_eq!(Some("The game is over, player 1 won".to_string()), position_factory::create("0101010").err());
+        let board = board_string('2', ["0000000", "0000000", "0000000", "0000000", "2220000", "1111000"]);
+        assert_eq!(Some("The game is over, player 1 won".to_string()), position_factory::create(board.as_str()).err());
+        let dimensions = Dimensions::new(4, 4).unwrap();
+        assert_eq!(Some("The game is over, it is a draw".to_string()),
+            position_factory::create_with_dimensions::<u64>("0123012312301230", dimensions).err());
+        // a game ending is still a game, and can be drawn
+        assert!(position_factory::create_game("0101010").is_ok());
+        assert!(position_factory::parse("0101010").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_moves_after_win() {
+        assert!(position_factory::create("01010101").is_err());
+    }
+
+    #[test]
+    fn test_rejects_floating_pieces() {
+        let board = board_string('1', ["0000000", "0000000", "0000000", "0000000", "1000000", "0200000"]);
+        assert!(position_factory::create(board.as_str()).is_err());
+    }
+
+    #[test]
+    fn test_board_string_win() {
+        let board = board_string('2', ["0000000", "0000000", "0000000", "0000000", "2220000", "1111000"]);
+        let position = position_factory::parse(board.as_str()).unwrap();
+        assert_eq!(GameStatus::Win { player: Player::One, line: [(0, 0), (1, 0), (2, 0), (3, 0)] }, position.status());
+
+        let board = board_string('2', ["0000000", "0000000", "0000000", "0000000", "2222000", "1111001"]);
+        assert!(position_factory::create(board.as_str()).is_err());
+    }
+
+    fn board_string(player_up_next: char, rows: [&str; 6]) -> String {
+        format!("{}{}", player_up_next, rows.concat())
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(Ok(512 << 20), transposition_table::parse_memory("512MB"));
+        assert_eq!(Ok(2 << 30), transposition_table::parse_memory("2gb"));
+        assert_eq!(Ok(1000), transposition_table::parse_memory("1000"));
+        assert!(transposition_table::parse_memory("lots").is_err());
+    }
+
+    #[test]
+    fn test_table_memory_budget() {
+        let table = TranspositionTable::with_memory(Dimensions::STANDARD, 1 << 20);
+        let (_, size) = table.utilization();
+        assert!(table.memory() <= 1 << 20);
+        assert!(size > 100_000);
+        // entries come in buckets of 4, and the number of buckets is prime
+        let buckets = size / 4;
+        assert_eq!(size, buckets * 4);
+        assert!((2..buckets).take_while(|i| i * i <= buckets).all(|i| buckets % i != 0));
+        assert_eq!(TranspositionTable::DEFAULT_MEMORY, TranspositionTable::new(Dimensions::STANDARD).memory());
+    }
+
+    const GLOBAL_FLAGS: &[Flag] = &[
+        Flag { name: "book", value: Some("PATH"), help: "" },
+        Flag { name: "weak", value: None, help: "" },
+    ];
+
+    const COMMANDS: &[Command] = &[
+        Command {
+            name: "solve",
+            arguments: &["position"],
+            about: "",
+            flags: &[Flag { name: "format", value: Some("text|json"), help: "" }],
+        },
+    ];
+
+    fn parse(args: &[&str]) -> Result<Args, CliError> {
+        Args::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>(), COMMANDS, GLOBAL_FLAGS)
+    }
+
+    #[test]
+    fn test_parse_command_line() {
+        let args = parse(&["--book", "a", "solve", "4455", "--weak", "--format=json", "--book", "b"]).unwrap();
+        assert_eq!("solve", args.command().unwrap().name);
+        assert_eq!(Ok("4455"), args.positional(0));
+        assert!(args.switch("weak"));
+        assert_eq!(Ok(Some(OutputFormat::Json)), args.value_as::<OutputFormat>("format"));
+        assert_eq!(vec!["a", "b"], args.values("book").collect::<Vec<&str>>());
+        assert!(parse(&["solve", "--help"]).unwrap().help());
+    }
+
+    #[test]
+    fn test_command_line_usage_errors() {
+        let usage_error = |args: &[&str]| parse(args).is_err_and(|error| error.exit_code() == CliError::USAGE_EXIT_CODE);
+        assert!(usage_error(&["frob"]));
+        assert!(usage_error(&["solve", "4455", "44"]));
+        assert!(usage_error(&["--format", "json", "solve"]));
+        assert!(usage_error(&["solve", "--book"]));
+        assert!(usage_error(&["solve", "--weak=yes"]));
+        assert!(parse(&["solve"]).unwrap().positional(0).is_err());
+        assert!(parse(&["solve", "--format", "xml"]).unwrap().value_as::<OutputFormat>("format").is_err());
+        assert_eq!(CliError::FAILED_EXIT_CODE, CliError::from("x is not a digit".to_string()).exit_code());
+    }
+}